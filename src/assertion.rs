@@ -0,0 +1,246 @@
+use std::str::FromStr;
+
+use hdrhistogram::Histogram;
+
+use crate::report::{self, Report};
+use crate::stats;
+
+/// What an assertion is about
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Metric {
+    Percentile(f64), // of the total time, e.g. p99
+    Mean,
+    Min,
+    Max,
+    RequestsPerSec,
+    Errors, // failed requests
+    Non2xx,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+/// A threshold on the results, e.g. p99<50ms, errors<0.1% or rps>5000.
+/// Times are in milliseconds.
+#[derive(Debug, PartialEq)]
+pub struct Assertion {
+    text: String,
+    metric: Metric,
+    op: Op,
+    threshold: f64,
+    percent: bool, // of all requests, rather than a count
+}
+
+impl Assertion {
+    /// Checks the final results, the total times are needed for the
+    /// percentiles the report doesn't have
+    pub fn check(&self, report: &Report, total: &Histogram<u64>) -> report::Assertion {
+        let actual = self.measure(report, total);
+        report::Assertion {
+            assertion: self.text.clone(),
+            actual: if actual.is_nan() {
+                "none".to_owned()
+            } else {
+                format!("{}{}", round(actual), self.unit())
+            },
+            passed: self.holds(actual),
+        }
+    }
+
+    /// Value of the metric in the unit of the threshold. Not a number if
+    /// there is nothing to measure, e.g. times without a response.
+    fn measure(&self, report: &Report, total: &Histogram<u64>) -> f64 {
+        let counters = &report.counters;
+        // connection failures are requests without a response, too
+        let requests = counters.complete + counters.failed - counters.failures.length;
+        let count = |n: usize| match (self.percent, requests) {
+            (false, _) => n as f64,
+            (true, 0) => f64::NAN,
+            (true, requests) => n as f64 * 100.0 / requests as f64,
+        };
+        match self.metric {
+            Metric::RequestsPerSec => report.throughput.requests_per_sec,
+            Metric::Errors => count(counters.failed),
+            Metric::Non2xx => count(counters.unsuccessful),
+            _ if total.is_empty() => f64::NAN,
+            Metric::Percentile(percentage) => stats::percentile(total, percentage),
+            Metric::Mean => stats::mean(total),
+            Metric::Min => stats::min(total),
+            Metric::Max => stats::max(total),
+        }
+    }
+
+    /// Whether the measured value is within the threshold. Never if it
+    /// couldn't be measured.
+    fn holds(&self, actual: f64) -> bool {
+        match self.op {
+            Op::Less => actual < self.threshold,
+            Op::LessOrEqual => actual <= self.threshold,
+            Op::Greater => actual > self.threshold,
+            Op::GreaterOrEqual => actual >= self.threshold,
+        }
+    }
+
+    fn unit(&self) -> &'static str {
+        match self.metric {
+            Metric::Percentile(_) | Metric::Mean | Metric::Min | Metric::Max => "ms",
+            Metric::RequestsPerSec => "/s",
+            Metric::Errors | Metric::Non2xx if self.percent => "%",
+            Metric::Errors | Metric::Non2xx => "",
+        }
+    }
+}
+
+impl FromStr for Assertion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid assertion '{}', expected e.g. p99<50ms", s);
+
+        let i = s.find(['<', '>']).ok_or_else(invalid)?;
+        let (metric, rest) = s.split_at(i);
+        let (op, value) = match rest.split_at(1) {
+            ("<", value) => match value.strip_prefix('=') {
+                Some(value) => (Op::LessOrEqual, value),
+                None => (Op::Less, value),
+            },
+            (_, value) => match value.strip_prefix('=') {
+                Some(value) => (Op::GreaterOrEqual, value),
+                None => (Op::Greater, value),
+            },
+        };
+        let metric = parse_metric(metric.trim()).ok_or_else(invalid)?;
+        let value = value.trim();
+
+        let (threshold, percent) = match metric {
+            Metric::Percentile(_) | Metric::Mean | Metric::Min | Metric::Max => {
+                (parse_time(value), false)
+            }
+            Metric::RequestsPerSec => (
+                value.strip_suffix("/s").unwrap_or(value).parse().ok(),
+                false,
+            ),
+            Metric::Errors | Metric::Non2xx => match value.strip_suffix('%') {
+                Some(percentage) => (percentage.parse().ok(), true),
+                None => (value.parse().ok(), false),
+            },
+        };
+        match threshold {
+            Some(threshold) if threshold >= 0.0 && f64::is_finite(threshold) => Ok(Assertion {
+                text: s.chars().filter(|c| !c.is_whitespace()).collect(),
+                metric,
+                op,
+                threshold,
+                percent,
+            }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+fn parse_metric(s: &str) -> Option<Metric> {
+    match s {
+        "mean" => Some(Metric::Mean),
+        "min" => Some(Metric::Min),
+        "max" => Some(Metric::Max),
+        "rps" => Some(Metric::RequestsPerSec),
+        "errors" => Some(Metric::Errors),
+        "non2xx" => Some(Metric::Non2xx),
+        _ => match s.strip_prefix('p')?.parse() {
+            Ok(percentage) if (0.0..=100.0).contains(&percentage) => {
+                Some(Metric::Percentile(percentage))
+            }
+            _ => None,
+        },
+    }
+}
+
+/// Milliseconds from e.g. 500us, 50ms or 1.5s. Milliseconds without a unit.
+fn parse_time(s: &str) -> Option<f64> {
+    let (n, millis) = if let Some(n) = s.strip_suffix("us") {
+        (n, 0.001)
+    } else if let Some(n) = s.strip_suffix("ms") {
+        (n, 1.0)
+    } else if let Some(n) = s.strip_suffix('s') {
+        (n, 1000.0)
+    } else {
+        (s, 1.0)
+    };
+    n.parse::<f64>().ok().map(|n| n * millis)
+}
+
+/// Three decimals like the report, but without trailing zeros
+fn round(n: f64) -> String {
+    let s = format!("{:.3}", n);
+    if s.contains('.') {
+        s.trim_end_matches('0').trim_end_matches('.').to_owned()
+    } else {
+        s
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assertion(s: &str) -> Assertion {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        let p99 = assertion("p99 < 50ms");
+        assert_eq!(Metric::Percentile(99.0), p99.metric);
+        assert_eq!(Op::Less, p99.op);
+        assert_eq!(50.0, p99.threshold);
+        assert_eq!("p99<50ms", p99.text);
+
+        let errors = assertion("errors<=0.1%");
+        assert_eq!(Metric::Errors, errors.metric);
+        assert_eq!(Op::LessOrEqual, errors.op);
+        assert_eq!(0.1, errors.threshold);
+        assert!(errors.percent);
+
+        let rps = assertion("rps>=5000");
+        assert_eq!(Metric::RequestsPerSec, rps.metric);
+        assert_eq!(Op::GreaterOrEqual, rps.op);
+        assert_eq!(5000.0, rps.threshold);
+
+        assert_eq!(Metric::Percentile(99.9), assertion("p99.9<1s").metric);
+        assert_eq!(1000.0, assertion("p99.9<1s").threshold);
+        assert_eq!(0.5, assertion("mean<500us").threshold);
+        assert_eq!(20.0, assertion("max<20").threshold);
+        assert!(!assertion("non2xx<10").percent);
+
+        assert!("p99".parse::<Assertion>().is_err());
+        assert!("p101<50ms".parse::<Assertion>().is_err());
+        assert!("latency<50ms".parse::<Assertion>().is_err());
+        assert!("p99<fast".parse::<Assertion>().is_err());
+        assert!("errors<-1".parse::<Assertion>().is_err());
+        assert!("rps>".parse::<Assertion>().is_err());
+    }
+
+    #[test]
+    fn test_holds() {
+        let p99 = assertion("p99<50ms");
+        assert!(p99.holds(49.9));
+        assert!(!p99.holds(50.0));
+        assert!(!p99.holds(f64::NAN));
+
+        assert!(assertion("p99<=50ms").holds(50.0));
+        assert!(!assertion("rps>5000").holds(5000.0));
+        assert!(assertion("rps>=5000").holds(5000.0));
+    }
+
+    #[test]
+    fn test_round() {
+        assert_eq!("12.346", round(12.3456));
+        assert_eq!("0.1", round(0.1));
+        assert_eq!("5000", round(5000.0));
+    }
+}
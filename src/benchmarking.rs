@@ -4,104 +4,427 @@ use std::io;
 use std::rc::Rc;
 use std::time::{Duration, Instant};
 
-use mio::event::{Event, Source};
+use mio::event::Event;
 use mio::{Events, Token};
+use rustls::TLSError;
 
-use super::connection::Connection;
-use super::connection::ConnectionState::{Connected, Connecting};
-use super::ctx::Ctx;
-use super::http::Response;
+use super::connection::ConnectionState;
+use super::connection::ConnectionState::{Connected, Connecting, Unconnected};
+use super::connection::{Connection, Stream};
+use super::ctx::{Ctx, Error, Failure, Warmup};
 use super::reporting::Reporter;
-use std::io::{Read, Write};
 
-pub fn benchmark<S: Write + Read + Source>(
+/// With a budget shared by threads, this often idle connections check if
+/// another thread has given back requests
+const SHARED_BUDGET_INTERVAL: Duration = Duration::from_millis(100);
+
+pub fn benchmark<S: Stream>(
     timelimit: Duration,
     ctx: &mut Ctx,
     connections: &mut HashMap<Token, Connection<S>>,
     reporter: Rc<RefCell<Reporter>>,
 ) -> io::Result<()> {
-    let start = Instant::now();
+    let mut start = Instant::now();
     let mut time_left = timelimit;
     let mut events = Events::with_capacity(128);
+    let mut step = 0;
 
-    reporter.borrow_mut().start();
+    let mut warmup = ctx.warmup;
+    let max_requests = ctx.max_requests();
+    // the warm-up doesn't use up the requests shared with other threads
+    let mut budget = match warmup {
+        Some(_) => ctx.budget.take(),
+        None => None,
+    };
+    match warmup {
+        Some(Warmup::Requests(requests)) => ctx.restart(requests),
+        Some(Warmup::Duration(_)) => ctx.restart(usize::MAX),
+        None => {}
+    }
+    begin(start, &mut step, ctx, connections, &reporter);
+    if warmup.is_some() {
+        reporter.borrow_mut().warm_up();
+    }
+
+    loop {
+        if !ctx.expect_more_responses() {
+            if warmup.take().is_none() {
+                break;
+            }
+            // the warm-up is over and none of its requests are in flight
+            ctx.restart(max_requests);
+            ctx.budget = budget.take();
+            start = Instant::now();
+            begin(start, &mut step, ctx, connections, &reporter);
+            send_on_idle(ctx, connections)?;
+            continue;
+        }
 
-    while ctx.expect_more_responses() {
-        ctx.poll(&mut events, Some(time_left))?;
+        let now = Instant::now();
+        let warmup_end = match warmup {
+            Some(Warmup::Duration(duration)) if ctx.send_more() => Some(start + duration),
+            _ => None,
+        };
+        // a request that is already due, but has no idle connection, waits
+        // for one to connect or to be done with its response
+        let idle = connections.values().any(|c| c.state == Connected);
+        let next_send = match &ctx.schedule {
+            Some(schedule) if ctx.send_more() => Some(schedule.next()),
+            _ => None,
+        }
+        .filter(|&next| next > now || idle);
+        let next_change = match &ctx.profile {
+            Some(profile) => profile.next_change(now - start).map(|d| start + d),
+            None => None,
+        };
+        let next_deadline = connections
+            .values()
+            .filter_map(|c| c.deadline())
+            .chain(next_send)
+            .chain(next_change)
+            .chain(warmup_end)
+            .chain(reporter.borrow().next_snapshot())
+            .min();
+        let mut poll_timeout = match next_deadline {
+            Some(deadline) => time_left.min(deadline.saturating_duration_since(now)),
+            None => time_left,
+        };
+        if ctx.budget.is_some() {
+            poll_timeout = poll_timeout.min(SHARED_BUDGET_INTERVAL);
+        }
+        ctx.poll(&mut events, Some(poll_timeout))?;
 
         for event in events.iter() {
             let token = event.token();
             match connections.get_mut(&token) {
-                Some(mut connection) => handle_connection_event(event, ctx, &mut connection)?,
+                Some(connection) => handle_connection_event(event, ctx, connection)?,
                 None => unreachable!(),
             }
         }
 
-        let elapsed = Instant::now() - start;
-        if elapsed > timelimit {
-            eprintln!("Timelimit exceeded");
-            break;
-        } else {
-            time_left = timelimit - elapsed;
+        let now = Instant::now();
+        for conn in connections.values_mut() {
+            if conn.deadline().is_some_and(|deadline| deadline <= now) {
+                let error = if conn.state == Connecting {
+                    Error::ConnectTimeout
+                } else {
+                    Error::ReadTimeout
+                };
+                fail(conn, ctx, error)?;
+            }
         }
+
+        if reporter
+            .borrow()
+            .next_snapshot()
+            .is_some_and(|due| due <= now)
+        {
+            snapshot(ctx, connections, &reporter);
+        }
+
+        if warmup_end.is_some_and(|end| end <= now) {
+            ctx.stop_sending();
+        }
+
+        if warmup.is_none() && ctx.profile.is_some() {
+            let elapsed = Instant::now() - start;
+            follow_profile(elapsed, &mut step, ctx, connections, &reporter);
+        }
+
+        if ctx.schedule.is_some() {
+            dispatch(ctx, connections)?;
+        } else if ctx.budget.is_some() {
+            send_on_idle(ctx, connections)?;
+        }
+
+        // the time limit is for the benchmark only, not the warm-up
+        let elapsed = match warmup {
+            Some(_) => Duration::ZERO,
+            None => Instant::now() - start,
+        };
+        if elapsed >= timelimit && ctx.send_more() {
+            // the time is up, only wait for the requests in flight, but
+            // not as part of the time taken
+            ctx.stop_sending();
+            reporter.borrow_mut().stop();
+        }
+        time_left = match timelimit.saturating_sub(elapsed) {
+            Duration::ZERO => Duration::MAX,
+            left => left,
+        };
     }
 
+    if reporter.borrow().next_snapshot().is_some() {
+        // what happened since the last one
+        snapshot(ctx, connections, &reporter);
+    }
     reporter.borrow_mut().end();
+    ctx.log.finish();
 
     Ok(())
 }
 
-pub fn handle_connection_event<S: Write + Read + Source>(
+pub fn handle_connection_event<S: Stream>(
     event: &Event,
     ctx: &mut Ctx,
     conn: &mut Connection<S>,
 ) -> io::Result<()> {
-    if event.is_writable() && conn.state == Connecting {
-        conn.set_state(Connected);
+    if conn.state == Connecting {
+        match conn.connect_result() {
+            Some(Ok(())) => conn.set_state(Connected),
+            Some(Err(e)) => return fail(conn, ctx, classify(&e, true)),
+            None => return Ok(()), // still connecting
+        }
     }
 
-    if event.is_writable() && ctx.send_more() && conn.state == Connected {
-        conn.send_request(ctx)?;
+    if event.is_writable() && conn.state == ConnectionState::Read {
+        // the rest of the request, then anything the stream has pending
+        if let Err(e) = conn.write_request(&ctx.payload).and_then(|_| conn.flush()) {
+            return fail(conn, ctx, classify(&e, false));
+        }
+    }
+
+    if event.is_writable() && ctx.may_send() && conn.state == Connected {
+        if let Err(e) = conn.send_request(ctx) {
+            return fail(conn, ctx, classify(&e, false));
+        }
     }
 
     if event.is_readable() {
         let mut buf = vec![0; 4096];
-        let (done, bytes_read) = conn.read_all(&mut buf);
+        let (done, bytes_read) = match conn.read_all(&mut buf) {
+            Ok(read) => read,
+            Err(e) => return fail(conn, ctx, classify(&e, false)),
+        };
 
         if bytes_read != 0 {
-            record_response(&buf[..bytes_read], conn, ctx);
-            conn.bytes_read(bytes_read);
+            ctx.log.data_received(conn.token, &buf[..bytes_read]);
+            if let Err(e) = conn.bytes_read(&buf[..bytes_read]) {
+                ctx.log
+                    .warning(format!("Failed to parse HTTP response: {}", e));
+                return fail(conn, ctx, Error::ParseError);
+            }
         }
-
         if done {
-            conn.finish_request();
-            conn.reset(ctx)?;
+            conn.closed();
+        }
+
+        if conn.is_response_complete() {
+            record_response(conn, ctx);
+            if !done && ctx.keep_alive && conn.can_keep_alive() {
+                conn.reuse();
+                ctx.keep_alive_response();
+                if ctx.may_send() {
+                    if let Err(e) = conn.send_request(ctx) {
+                        return fail(conn, ctx, classify(&e, false));
+                    }
+                }
+            } else {
+                conn.finish_request();
+                reconnect(conn, ctx);
+            }
+        } else if done {
+            return fail(conn, ctx, Error::PrematureClose);
         }
     }
     Ok(())
 }
 
-fn record_response<S>(received_data: &[u8], conn: &Connection<S>, ctx: &mut Ctx) {
-    if !conn.is_reading_response() {
-        // first bytes, check http response code
+/// Starts timing the benchmark, or the warm-up, and the schedule and
+/// profile with it
+fn begin<S: Stream>(
+    start: Instant,
+    step: &mut usize,
+    ctx: &mut Ctx,
+    connections: &mut HashMap<Token, Connection<S>>,
+    reporter: &Rc<RefCell<Reporter>>,
+) {
+    reporter.borrow_mut().start();
+    if let Some(schedule) = &mut ctx.schedule {
+        schedule.start(start);
+    }
+    if let Some(profile) = &ctx.profile {
+        *step = 0;
+        reporter
+            .borrow_mut()
+            .begin_step(0, profile.step_level(0), 0);
+        follow_profile(Duration::ZERO, step, ctx, connections, reporter);
+    }
+}
+
+/// Sends a snapshot of the progress
+fn snapshot<S: Stream>(
+    ctx: &Ctx,
+    connections: &HashMap<Token, Connection<S>>,
+    reporter: &Rc<RefCell<Reporter>>,
+) {
+    let open = connections
+        .values()
+        .filter(|c| c.state != Unconnected)
+        .count();
+    reporter.borrow_mut().snapshot(ctx.failures.total(), open);
+}
 
-        // first response from this server, store some things
-        let first_response = ctx.server_name.is_none();
+/// Adjusts the load to the profile: changes the rate or, without a fixed
+/// rate, opens more connections. Begins a new step in the report when the
+/// profile reaches it.
+fn follow_profile<S: Stream>(
+    elapsed: Duration,
+    step: &mut usize,
+    ctx: &mut Ctx,
+    connections: &mut HashMap<Token, Connection<S>>,
+    reporter: &Rc<RefCell<Reporter>>,
+) {
+    let profile = ctx.profile.as_ref().expect("Missing load profile");
+    let level = profile.level(elapsed);
+    let current = profile.step(elapsed);
+    if current != *step {
+        *step = current;
+        let level = profile.step_level(current);
+        reporter
+            .borrow_mut()
+            .begin_step(current, level, ctx.failures.total());
+    }
 
-        if let Ok(resp) = Response::parse(received_data, !first_response) {
-            if first_response {
-                ctx.server_name = Some(resp.server.unwrap_or_default());
-                ctx.doclen = resp.body_length;
+    if let Some(schedule) = &mut ctx.schedule {
+        schedule.set_rate(level, Instant::now());
+        return;
+    }
+
+    let missing = (level.round() as usize).saturating_sub(connections.len());
+    let mut opened = vec![];
+    if let Some(conn) = connections.values().next() {
+        for _ in 0..missing.min(ctx.requests_left()) {
+            match conn.open_another(ctx) {
+                Ok(new_conn) => opened.push(new_conn),
+                Err(e) => ctx.error(classify(&e, true)),
             }
-            if (200..300).contains(&resp.status) {
-                ctx.successful_response();
-            } else {
-                eprintln!("HTTP Response Code {}", resp.status);
-                ctx.unsuccessful_response();
+        }
+    }
+    for conn in opened {
+        connections.insert(conn.token, conn);
+    }
+}
+
+/// Sends requests on connections that are connected, but idle, as long as
+/// requests may be sent
+fn send_on_idle<S: Stream>(
+    ctx: &mut Ctx,
+    connections: &mut HashMap<Token, Connection<S>>,
+) -> io::Result<()> {
+    for conn in connections.values_mut() {
+        if !ctx.may_send() {
+            break;
+        }
+        if conn.state == Connected {
+            if let Err(e) = conn.send_request(ctx) {
+                fail(conn, ctx, classify(&e, false))?;
             }
-        } else {
-            eprintln!("Failed to parse HTTP Header");
-            ctx.failed_response();
         }
     }
+    Ok(())
+}
+
+/// Sends the requests that are due on idle connections and opens more
+/// connections if all of them are busy, up to the maximum
+fn dispatch<S: Stream>(
+    ctx: &mut Ctx,
+    connections: &mut HashMap<Token, Connection<S>>,
+) -> io::Result<()> {
+    send_on_idle(ctx, connections)?;
+    if !ctx.may_send() {
+        return Ok(());
+    }
+
+    let due = match &ctx.schedule {
+        Some(schedule) => schedule.due(Instant::now()).min(ctx.requests_left()),
+        None => 0,
+    };
+    let connecting = connections
+        .values()
+        .filter(|c| c.state == Connecting)
+        .count();
+    let room = ctx.max_connections.saturating_sub(connections.len());
+    let mut opened = vec![];
+    if let Some(conn) = connections.values().next() {
+        for _ in connecting..due.min(connecting + room) {
+            match conn.open_another(ctx) {
+                Ok(new_conn) => opened.push(new_conn),
+                Err(e) => ctx.error(classify(&e, true)),
+            }
+        }
+    }
+    for conn in opened {
+        connections.insert(conn.token, conn);
+    }
+    Ok(())
+}
+
+/// Ends the request in flight, if any, and connects again
+fn fail<S: Stream>(conn: &mut Connection<S>, ctx: &mut Ctx, error: Error) -> io::Result<()> {
+    if conn.is_reused() && !conn.is_reading_response() && conn.state == ConnectionState::Read {
+        // server has shut down an idle keep-alive connection, so
+        // our request never happened and must be sent again
+        conn.discard_request(ctx);
+        conn.finish_request();
+    } else if conn.state == ConnectionState::Read || conn.state == Connecting {
+        ctx.error(error);
+        conn.fail_request();
+    } else {
+        conn.finish_request();
+    }
+    reconnect(conn, ctx);
+    Ok(())
+}
+
+/// Opens a new connection, as long as there are requests left to send.
+/// Failed attempts use up requests.
+fn reconnect<S: Stream>(conn: &mut Connection<S>, ctx: &mut Ctx) {
+    while let Err(e) = conn.reset(ctx) {
+        conn.set_state(Unconnected);
+        if !ctx.send_more() {
+            break;
+        }
+        ctx.error(classify(&e, true));
+    }
+}
+
+/// Finds out what kind of error happened on the connection
+fn classify(e: &io::Error, connecting: bool) -> Error {
+    let timeout = e.kind() == io::ErrorKind::TimedOut;
+    if e.get_ref().is_some_and(|inner| inner.is::<TLSError>()) {
+        Error::TlsError
+    } else if connecting && timeout {
+        Error::ConnectTimeout
+    } else if connecting {
+        Error::ConnectRefused
+    } else if timeout {
+        Error::ReadTimeout
+    } else if e.kind() == io::ErrorKind::UnexpectedEof {
+        Error::PrematureClose
+    } else {
+        Error::ConnectionReset
+    }
+}
+
+/// Counts a complete response
+fn record_response<S: Stream>(conn: &Connection<S>, ctx: &mut Ctx) {
+    let response = conn.response();
+    let head = response.head().expect("Complete response must have a head");
+
+    if ctx.server_name.is_none() {
+        // first response from this server, store some things
+        ctx.server_name = Some(head.header("server").unwrap_or_default().to_owned());
+        ctx.doclen = Some(response.body_length());
+        ctx.tls_protocol = conn.tls_protocol();
+    } else if !ctx.accept_variable_length && ctx.doclen != Some(response.body_length()) {
+        ctx.failed_response(Failure::Length);
+    }
+
+    ctx.log.response_code(conn.token, head.status);
+    if !(200..300).contains(&head.status) {
+        ctx.log
+            .warning(format!("Response code not 2xx ({})", head.status));
+    }
+    ctx.response(head.status);
 }
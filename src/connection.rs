@@ -8,33 +8,78 @@ use mio::Token;
 use ConnectionState::*;
 
 use super::ctx::Ctx;
+use super::http::ResponseReader;
 use super::reporting::Reporter;
 use mio::event::Source;
+use mio::net::TcpStream;
 use std::mem;
 use std::net::SocketAddr;
+use std::time::{Duration, Instant};
 
 pub struct Connection<S> {
     pub token: Token,
     addr: SocketAddr,
     stream: S,
-    factory: Box<dyn Fn(SocketAddr) -> io::Result<S>>,
+    factory: Rc<dyn Fn(SocketAddr) -> io::Result<S>>,
     pub state: ConnectionState,
     bytes_sent: usize,
     pub bytes_received: usize,
     sent_requests: usize,
+    written: usize, // of the request in flight
     reading_response: bool,
+    response: ResponseReader,
+    reused: bool, // whether a previous request was sent over this connection
+    timeout: Duration,
+    since: Instant, // when connecting or the current request started
     reporter: Rc<RefCell<Reporter>>,
 }
 
+/// A connection to the server, either plain or encrypted
+pub trait Stream: Read + Write + Source {
+    /// Negotiated TLS protocol version and cipher, if any
+    fn tls_protocol(&self) -> Option<String> {
+        None
+    }
+
+    /// Error of the underlying socket, e.g. from connecting
+    fn take_error(&self) -> io::Result<Option<io::Error>>;
+
+    fn peer_addr(&self) -> io::Result<SocketAddr>;
+}
+
+impl Stream for TcpStream {
+    fn take_error(&self) -> io::Result<Option<io::Error>> {
+        TcpStream::take_error(self)
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        TcpStream::peer_addr(self)
+    }
+}
+
 impl<S> Connection<S>
 where
-    S: Read + Write + Source,
+    S: Stream,
 {
     pub fn new(
         ctx: &mut Ctx,
         addr: SocketAddr,
         factory: Box<dyn Fn(SocketAddr) -> io::Result<S>>,
         reporter: Rc<RefCell<Reporter>>,
+    ) -> io::Result<Connection<S>> {
+        Connection::with_factory(ctx, addr, Rc::from(factory), reporter)
+    }
+
+    /// Opens one more connection to the same server
+    pub fn open_another(&self, ctx: &mut Ctx) -> io::Result<Connection<S>> {
+        Connection::with_factory(ctx, self.addr, self.factory.clone(), self.reporter.clone())
+    }
+
+    fn with_factory(
+        ctx: &mut Ctx,
+        addr: SocketAddr,
+        factory: Rc<dyn Fn(SocketAddr) -> io::Result<S>>,
+        reporter: Rc<RefCell<Reporter>>,
     ) -> io::Result<Connection<S>> {
         let token = ctx.next_token();
         let mut connection = Connection {
@@ -46,7 +91,12 @@ where
             bytes_sent: 0,
             bytes_received: 0,
             sent_requests: 0,
+            written: 0,
             reading_response: false,
+            response: ResponseReader::new(ctx.head_request),
+            reused: false,
+            timeout: ctx.timeout,
+            since: Instant::now(),
             reporter,
         };
         ctx.register(token, &mut connection.stream)?;
@@ -54,23 +104,45 @@ where
         Ok(connection)
     }
 
+    /// Replaces the stream with a new connection to the server. If that
+    /// fails, the old stream is kept.
     pub fn reset(&mut self, ctx: &mut Ctx) -> io::Result<()> {
-        ctx.deregister(&mut self.stream)?;
-        let _ = mem::replace(&mut self.stream, (self.factory)(self.addr)?);
+        let stream = (self.factory)(self.addr)?;
+        let _ = ctx.deregister(&mut self.stream);
+        let _ = mem::replace(&mut self.stream, stream);
         // prev stream should be dropped here
+        self.reused = false;
         self.set_state(Unconnected);
         self.set_state(Connecting);
         ctx.register(self.token, &mut self.stream)
     }
+
+    pub fn tls_protocol(&self) -> Option<String> {
+        self.stream.tls_protocol()
+    }
+
+    /// Outcome of connecting, or none while still connecting
+    pub fn connect_result(&self) -> Option<io::Result<()>> {
+        match self.stream.take_error() {
+            Ok(Some(e)) | Err(e) => Some(Err(e)),
+            Ok(None) => match self.stream.peer_addr() {
+                Ok(_) => Some(Ok(())),
+                Err(e) if e.kind() == ErrorKind::NotConnected => None,
+                Err(e) => Some(Err(e)),
+            },
+        }
+    }
 }
 
 impl<S: Read> Connection<S> {
-    pub fn read_all(&mut self, buf: &mut Vec<u8>) -> (bool, usize) {
+    /// Reads everything that is available. Returns whether the server has
+    /// closed the connection and how many bytes were read.
+    pub fn read_all(&mut self, buf: &mut Vec<u8>) -> io::Result<(bool, usize)> {
         let mut bytes_read = 0;
         loop {
             match self.stream.read(&mut buf[bytes_read..]) {
                 Ok(0) => {
-                    return (true, bytes_read);
+                    return Ok((true, bytes_read));
                 }
                 Ok(n) => {
                     bytes_read += n;
@@ -78,9 +150,9 @@ impl<S: Read> Connection<S> {
                         buf.resize(buf.len() + 1024, 0);
                     }
                 }
-                Err(e) if e.kind() == ErrorKind::WouldBlock => return (false, bytes_read),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok((false, bytes_read)),
                 Err(e) if e.kind() == ErrorKind::Interrupted => continue,
-                Err(_) => return (false, bytes_read),
+                Err(e) => return Err(e),
             };
         }
     }
@@ -89,18 +161,76 @@ impl<S: Read> Connection<S> {
 impl<S> Connection<S> {
     pub fn finish_request(&mut self) {
         self.reading_response = false;
+        self.response.reset();
+    }
+
+    /// Keeps the connection open for the next request after a complete response
+    pub fn reuse(&mut self) {
+        self.finish_request();
+        self.reused = true;
+        self.set_state(Connected);
     }
 
-    pub fn bytes_read(&mut self, nbytes: usize) {
+    /// Feeds received data to the response parser, fails if the response
+    /// is not valid HTTP
+    pub fn bytes_read(&mut self, data: &[u8]) -> Result<(), String> {
+        if !self.reading_response {
+            self.reporter.borrow_mut().response_started(&self.token);
+        }
         self.reading_response = true;
-        self.bytes_received += nbytes;
+        self.bytes_received += data.len();
+        let body = self.response.feed(data);
+        self.reporter.borrow_mut().bytes_received(
+            &self.token,
+            data.len(),
+            *body.as_ref().unwrap_or(&0),
+        );
+        body.map(|_| ())
+    }
+
+    /// The server has closed the connection
+    pub fn closed(&mut self) {
+        self.response.close();
+    }
+
+    pub fn response(&self) -> &ResponseReader {
+        &self.response
+    }
+
+    /// Gives up on the request in flight, which is then not timed
+    pub fn fail_request(&mut self) {
+        self.finish_request();
+        self.reporter.borrow_mut().request_failed(&self.token);
     }
 
     pub fn is_reading_response(&self) -> bool {
         self.reading_response
     }
 
+    pub fn is_response_complete(&self) -> bool {
+        self.response.is_complete()
+    }
+
+    pub fn can_keep_alive(&self) -> bool {
+        self.response.keep_alive()
+    }
+
+    pub fn is_reused(&self) -> bool {
+        self.reused
+    }
+
+    /// When connecting or waiting for the response takes too long
+    pub fn deadline(&self) -> Option<Instant> {
+        match self.state {
+            Connecting | Read => Some(self.since + self.timeout),
+            Unconnected | Connected => None,
+        }
+    }
+
     pub fn set_state(&mut self, new_state: ConnectionState) {
+        if new_state == Connecting || new_state == Read {
+            self.since = Instant::now();
+        }
         self.state = new_state;
         self.reporter
             .borrow_mut()
@@ -112,14 +242,63 @@ impl<S> Connection<S>
 where
     S: Write,
 {
+    /// Sends the next request, if there is one left. If that fails, the
+    /// request still counts as sent and is in flight, just like one the
+    /// server never answers.
     pub fn send_request(&mut self, ctx: &mut Ctx) -> io::Result<()> {
-        self.stream.write_all(&ctx.payload)?;
-        ctx.sent_requests += 1;
+        if !ctx.take_request() {
+            return Ok(());
+        }
         self.sent_requests += 1;
+        if let Some(schedule) = &mut ctx.schedule {
+            let scheduled = schedule.sent(Instant::now());
+            self.reporter
+                .borrow_mut()
+                .request_scheduled(&self.token, scheduled);
+        }
         self.bytes_sent += ctx.payload.len();
+        self.reporter
+            .borrow_mut()
+            .bytes_sent(&self.token, ctx.payload.len());
+        self.response = ResponseReader::new(ctx.head_request);
         self.set_state(Read);
+        ctx.log.request_sent(self.token, &ctx.payload);
+        self.written = 0;
+        self.write_request(&ctx.payload)
+    }
+
+    /// Writes as much of the request in flight as the stream takes. The
+    /// rest is written when the stream is writable again.
+    pub fn write_request(&mut self, payload: &[u8]) -> io::Result<()> {
+        while self.written < payload.len() {
+            match self.stream.write(&payload[self.written..]) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(n) => self.written += n,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
         Ok(())
     }
+
+    /// Writes data the stream may still have pending, e.g. from a TLS handshake
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+
+    /// Takes back a request that the server never responded to, so it is
+    /// neither counted nor timed.
+    pub fn discard_request(&mut self, ctx: &mut Ctx) {
+        ctx.give_back_request();
+        self.sent_requests -= 1;
+        if let Some(schedule) = &mut ctx.schedule {
+            schedule.discarded();
+        }
+        self.bytes_sent -= ctx.payload.len();
+        self.state = Connected;
+        self.reporter.borrow_mut().request_discarded(&self.token);
+    }
 }
 
 #[derive(PartialEq, Debug)]
@@ -1,19 +1,154 @@
+use std::collections::BTreeMap;
 use std::io;
-use std::time::Duration;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use mio::event::Source;
 use mio::{Events, Interest, Poll, Token};
 
+use crate::log::Log;
+use crate::profile::Profile;
+use crate::schedule::Schedule;
+
+/// Why a request failed, categorized like ab does
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Failure {
+    Connect,
+    Receive,
+    Length, // body differs from the first response
+    Exception,
+}
+
+/// What exactly went wrong with a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Error {
+    ConnectRefused, // or the server is unreachable otherwise
+    ConnectTimeout,
+    ConnectionReset, // or the connection broke otherwise
+    ReadTimeout,
+    PrematureClose, // server closed the connection before the response was complete
+    ParseError,
+    TlsError,
+}
+
+impl Error {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Error::ConnectRefused => "connect_refused",
+            Error::ConnectTimeout => "connect_timeout",
+            Error::ConnectionReset => "connection_reset",
+            Error::ReadTimeout => "read_timeout",
+            Error::PrematureClose => "premature_close",
+            Error::ParseError => "parse_error",
+            Error::TlsError => "tls_error",
+        }
+    }
+
+    fn failure(&self) -> Failure {
+        match self {
+            Error::ConnectRefused | Error::ConnectTimeout => Failure::Connect,
+            Error::ConnectionReset
+            | Error::ReadTimeout
+            | Error::PrematureClose
+            | Error::TlsError => Failure::Receive,
+            Error::ParseError => Failure::Exception,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Failures {
+    pub connect: usize,
+    pub receive: usize,
+    pub length: usize,
+    pub exceptions: usize,
+}
+
+impl Failures {
+    pub fn total(&self) -> usize {
+        self.connect + self.receive + self.length + self.exceptions
+    }
+}
+
+/// Requests that all threads send together
+pub struct Budget {
+    max: usize,
+    taken: AtomicUsize,
+}
+
+impl Budget {
+    pub fn new(max: usize) -> Self {
+        Budget {
+            max,
+            taken: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    /// Takes one request, unless there are none left
+    fn take(&self) -> bool {
+        self.taken
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |taken| {
+                if taken < self.max {
+                    Some(taken + 1)
+                } else {
+                    None
+                }
+            })
+            .is_ok()
+    }
+
+    /// Takes one request, even if that exceeds the budget
+    fn use_up(&self) {
+        self.taken.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn give_back(&self) {
+        self.taken.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    fn left(&self) -> usize {
+        self.max.saturating_sub(self.taken.load(Ordering::SeqCst))
+    }
+}
+
+/// Requests before the benchmark that don't count
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Warmup {
+    Requests(usize),
+    Duration(Duration),
+}
+
 pub struct Ctx {
     pub successful_responses: usize,
     pub unsuccessful_responses: usize,
-    pub failed_responses: usize,
+    pub failures: Failures,
+    pub errors: BTreeMap<Error, usize>,
+    pub status_codes: BTreeMap<u16, usize>,
     pub sent_requests: usize,
+    pub keep_alive_responses: usize,
     pub payload: Vec<u8>,
     pub concurrency: usize,
     pub server_name: Option<String>,
     pub doclen: Option<usize>,
-    max_requests: usize,
+    pub tls_protocol: Option<String>,
+    pub keep_alive: bool,
+    pub head_request: bool,
+    pub posting: bool,
+    pub accept_variable_length: bool,
+    pub timeout: Duration, // for connecting and for each response
+    pub log: Log,
+    pub schedule: Option<Schedule>, // for a fixed request rate
+    pub max_connections: usize,     // to keep up the rate, at least the concurrency
+    pub profile: Option<Profile>,   // for ramping up the load
+    pub warmup: Option<Warmup>,
+    pub budget: Option<Arc<Budget>>, // shared with the other threads
+    max_requests: usize,             // usize::MAX until the time limit
+    stopped: bool,                   // sending, e.g. when the time is up
     poll: Poll,
     token: Token,
 }
@@ -26,33 +161,155 @@ impl Ctx {
             sent_requests: 0,
             successful_responses: 0,
             unsuccessful_responses: 0,
-            failed_responses: 0,
+            failures: Failures::default(),
+            errors: BTreeMap::new(),
+            status_codes: BTreeMap::new(),
+            keep_alive_responses: 0,
             server_name: None,
             doclen: None,
+            tls_protocol: None,
+            keep_alive: false,
+            head_request: false,
+            posting: false,
+            accept_variable_length: false,
+            timeout: Duration::from_secs(30),
+            log: Log::new(0),
+            schedule: None,
+            max_connections: concurrency,
+            profile: None,
+            warmup: None,
+            budget: None,
             max_requests,
+            stopped: false,
             concurrency,
             payload,
         })
     }
 
+    /// Responses that are complete, i.e. including those with a wrong length,
+    /// and requests that failed otherwise
     pub fn total_responses(&self) -> usize {
-        self.failed_responses + self.successful_responses + self.unsuccessful_responses
+        let failures = &self.failures;
+        self.successful_responses
+            + self.unsuccessful_responses
+            + failures.connect
+            + failures.receive
+            + failures.exceptions
     }
 
+    pub fn max_requests(&self) -> usize {
+        self.max_requests
+    }
+
+    /// Discards all counters and what is known about the server, e.g. after
+    /// the warm-up, and expects this many requests from now on
+    pub fn restart(&mut self, max_requests: usize) {
+        self.successful_responses = 0;
+        self.unsuccessful_responses = 0;
+        self.failures = Failures::default();
+        self.errors.clear();
+        self.status_codes.clear();
+        self.sent_requests = 0;
+        self.keep_alive_responses = 0;
+        self.server_name = None;
+        self.doclen = None;
+        self.tls_protocol = None;
+        self.max_requests = max_requests;
+        self.stopped = false;
+    }
+
+    /// Sends no more requests, only waits for those in flight
+    pub fn stop_sending(&mut self) {
+        self.stopped = true;
+    }
+
+    /// Whether requests are in flight or left to send, with a shared budget
+    /// until the other threads have used it up, too
     pub fn expect_more_responses(&self) -> bool {
-        self.total_responses() < self.max_requests
+        self.total_responses() < self.sent_requests + self.failures.connect || self.send_more()
+    }
+
+    /// Counts the next request as sent, unless there are none left, e.g.
+    /// because other threads have sent them
+    pub fn take_request(&mut self) -> bool {
+        if !self.send_more() || !self.budget.as_ref().is_none_or(|budget| budget.take()) {
+            return false;
+        }
+        self.sent_requests += 1;
+        true
     }
 
-    pub fn successful_response(&mut self) {
-        self.successful_responses += 1;
+    /// Takes back a request that was never answered, it will be sent again
+    pub fn give_back_request(&mut self) {
+        self.sent_requests -= 1;
+        if let Some(budget) = &self.budget {
+            budget.give_back();
+        }
     }
 
-    pub fn unsuccessful_response(&mut self) {
-        self.unsuccessful_responses += 1;
+    /// Adds up the counters of another thread. Its first response only
+    /// counts if there was none here.
+    pub fn merge(&mut self, other: Ctx) {
+        self.successful_responses += other.successful_responses;
+        self.unsuccessful_responses += other.unsuccessful_responses;
+        self.failures.connect += other.failures.connect;
+        self.failures.receive += other.failures.receive;
+        self.failures.length += other.failures.length;
+        self.failures.exceptions += other.failures.exceptions;
+        for (error, count) in other.errors {
+            *self.errors.entry(error).or_insert(0) += count;
+        }
+        for (status, count) in other.status_codes {
+            *self.status_codes.entry(status).or_insert(0) += count;
+        }
+        self.sent_requests += other.sent_requests;
+        self.keep_alive_responses += other.keep_alive_responses;
+        self.concurrency += other.concurrency;
+        if self.server_name.is_none() {
+            self.server_name = other.server_name;
+            self.doclen = other.doclen;
+            self.tls_protocol = other.tls_protocol;
+        }
+        if let (Some(schedule), Some(other)) = (&mut self.schedule, &other.schedule) {
+            schedule.merge(other);
+        }
+        self.token.0 += other.token.0;
     }
 
-    pub fn failed_response(&mut self) {
-        self.failed_responses += 1;
+    /// Counts a complete response with this status code
+    pub fn response(&mut self, status: u16) {
+        *self.status_codes.entry(status).or_insert(0) += 1;
+        if (200..300).contains(&status) {
+            self.successful_responses += 1;
+        } else {
+            self.unsuccessful_responses += 1;
+        }
+    }
+
+    pub fn failed_response(&mut self, failure: Failure) {
+        let failures = &mut self.failures;
+        match failure {
+            Failure::Connect => {
+                failures.connect += 1;
+                if let Some(budget) = &self.budget {
+                    budget.use_up();
+                }
+            }
+            Failure::Receive => failures.receive += 1,
+            Failure::Length => failures.length += 1,
+            Failure::Exception => failures.exceptions += 1,
+        }
+    }
+
+    /// Counts a request that failed because of an error. Failed connection
+    /// attempts use up a request, too.
+    pub fn error(&mut self, error: Error) {
+        *self.errors.entry(error).or_insert(0) += 1;
+        self.failed_response(error.failure());
+    }
+
+    pub fn keep_alive_response(&mut self) {
+        self.keep_alive_responses += 1;
     }
 
     pub fn poll(&mut self, events: &mut Events, timeout: Option<Duration>) -> io::Result<()> {
@@ -69,6 +326,10 @@ impl Ctx {
         self.poll.registry().deregister(source)
     }
 
+    pub fn connections_opened(&self) -> usize {
+        self.token.0
+    }
+
     pub fn next_token(&mut self) -> Token {
         let next = self.token.0;
         self.token.0 += 1;
@@ -76,6 +337,102 @@ impl Ctx {
     }
 
     pub fn send_more(&self) -> bool {
-        self.max_requests > self.sent_requests
+        self.requests_left() > 0
+    }
+
+    /// Requests that have not been sent yet
+    pub fn requests_left(&self) -> usize {
+        if self.stopped {
+            return 0;
+        }
+        let left = self
+            .max_requests
+            .saturating_sub(self.sent_requests + self.failures.connect);
+        match &self.budget {
+            Some(budget) => left.min(budget.left()),
+            None => left,
+        }
+    }
+
+    /// Whether a request may be sent now, which with a fixed rate is only
+    /// when the next one is due
+    pub fn may_send(&self) -> bool {
+        self.send_more()
+            && match &self.schedule {
+                Some(schedule) => schedule.due(Instant::now()) > 0,
+                None => true,
+            }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shared_budget() {
+        let budget = Arc::new(Budget::new(3));
+        let mut ctxs: Vec<Ctx> = (0..2)
+            .map(|_| {
+                let mut ctx = Ctx::new(vec![], 3, 1).unwrap();
+                ctx.budget = Some(budget.clone());
+                ctx
+            })
+            .collect();
+
+        assert!(ctxs[0].take_request());
+        assert!(ctxs[1].take_request());
+        assert!(ctxs[0].take_request());
+        assert!(!ctxs[1].take_request());
+        assert!(!ctxs[1].send_more());
+
+        ctxs[0].give_back_request();
+        assert!(ctxs[1].take_request());
+        assert_eq!(1, ctxs[0].sent_requests);
+        assert_eq!(2, ctxs[1].sent_requests);
+
+        // the other thread's request is still in flight
+        assert!(ctxs[1].expect_more_responses());
+        ctxs[1].response(200);
+        ctxs[1].response(200);
+        assert!(!ctxs[1].expect_more_responses());
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut ctx = Ctx::new(vec![], 4, 2).unwrap();
+        ctx.schedule = Some(Schedule::new(10.0));
+        ctx.next_token();
+        ctx.next_token();
+        ctx.take_request();
+        ctx.response(200);
+        ctx.error(Error::ReadTimeout);
+
+        let mut other = Ctx::new(vec![], 4, 1).unwrap();
+        other.schedule = Some(Schedule::new(5.0));
+        other.server_name = Some("hyper".to_owned());
+        other.doclen = Some(12);
+        other.next_token();
+        other.take_request();
+        other.take_request();
+        other.response(200);
+        other.response(404);
+        other.error(Error::ReadTimeout);
+        other.error(Error::ConnectRefused);
+
+        ctx.merge(other);
+        assert_eq!(3, ctx.sent_requests);
+        assert_eq!(2, ctx.successful_responses);
+        assert_eq!(1, ctx.unsuccessful_responses);
+        assert_eq!(Some(&2), ctx.status_codes.get(&200));
+        assert_eq!(Some(&2), ctx.errors.get(&Error::ReadTimeout));
+        assert_eq!(Some(&1), ctx.errors.get(&Error::ConnectRefused));
+        assert_eq!(2, ctx.failures.receive);
+        assert_eq!(1, ctx.failures.connect);
+        assert_eq!(3, ctx.concurrency);
+        assert_eq!(3, ctx.connections_opened());
+        assert_eq!(Some("hyper".to_owned()), ctx.server_name);
+        assert_eq!(Some(12), ctx.doclen);
+        assert_eq!(15.0, ctx.schedule.unwrap().rate());
     }
 }
@@ -1,116 +1,700 @@
+use std::mem;
+
 use url::{Position, Url};
 
-pub struct Response {
+/// Upper bound for the head of a response and for lines in a chunked body
+const MAX_HEAD_SIZE: usize = 64 * 1024;
+
+/// Status line and headers of a response
+#[derive(Debug)]
+pub struct Head {
+    pub version: u8, // minor version, i.e. 1 for HTTP/1.1
     pub status: u16,
-    pub server: Option<String>, // Server header
-    pub body_length: Option<usize>,
+    pub headers: Vec<(String, String)>,
+}
+
+impl Head {
+    fn parse(head: &[u8]) -> Result<Self, String> {
+        let head = String::from_utf8_lossy(head);
+        let mut lines = head.lines();
+        let (version, status) = parse_status_line(lines.next().unwrap_or_default())?;
+
+        let mut headers: Vec<(String, String)> = vec![];
+        for line in lines.take_while(|line| !line.is_empty()) {
+            if line.starts_with(' ') || line.starts_with('\t') {
+                // obsolete line folding, continues the previous header
+                match headers.last_mut() {
+                    Some((_, value)) => {
+                        value.push(' ');
+                        value.push_str(line.trim());
+                    }
+                    None => return Err(format!("Invalid header line: {}", line)),
+                }
+                continue;
+            }
+            match line.split_once(':') {
+                Some((name, value))
+                    if !name.is_empty() && !name.bytes().any(|b| b.is_ascii_whitespace()) =>
+                {
+                    headers.push((name.to_owned(), value.trim().to_owned()))
+                }
+                _ => return Err(format!("Invalid header line: {}", line)),
+            }
+        }
+
+        Ok(Head {
+            version,
+            status,
+            headers,
+        })
+    }
+
+    /// Value of the first header with this name, ignoring case
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Values of all headers with this name, ignoring case
+    pub fn headers<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> {
+        self.headers
+            .iter()
+            .filter(move |(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn has_token(&self, name: &str, token: &str) -> bool {
+        self.headers(name)
+            .flat_map(|value| value.split(','))
+            .any(|t| t.trim().eq_ignore_ascii_case(token))
+    }
+
+    fn content_length(&self) -> Result<Option<usize>, String> {
+        let mut content_length = None;
+        for value in self.headers("content-length").flat_map(|v| v.split(',')) {
+            let length = value
+                .trim()
+                .parse()
+                .map_err(|_| format!("Invalid Content-Length: {}", value))?;
+            match content_length {
+                Some(previous) if previous != length => {
+                    return Err("Conflicting Content-Length headers".into())
+                }
+                _ => content_length = Some(length),
+            }
+        }
+        Ok(content_length)
+    }
+
+    fn is_chunked(&self) -> bool {
+        self.headers("transfer-encoding")
+            .flat_map(|value| value.split(','))
+            .last()
+            .is_some_and(|coding| coding.trim().eq_ignore_ascii_case("chunked"))
+    }
+}
+
+/// Parses e.g. `HTTP/1.1 200 OK` into the minor version and the status code
+fn parse_status_line(line: &str) -> Result<(u8, u16), String> {
+    let digit = |b: u8| (b as char).to_digit(10);
+    if let Some(rest) = line.strip_prefix("HTTP/1.") {
+        if let [version, b' ', a, b, c, reason @ ..] = rest.as_bytes() {
+            if let (Some(version), Some(a), Some(b), Some(c)) =
+                (digit(*version), digit(*a), digit(*b), digit(*c))
+            {
+                if reason.is_empty() || reason[0] == b' ' {
+                    return Ok((version as u8, (a * 100 + b * 10 + c) as u16));
+                }
+            }
+        }
+    }
+    Err(format!("Invalid status line: {}", line))
 }
 
-impl Response {
-    pub fn parse(resp: &[u8], status_only: bool) -> Result<Self, String> {
-        let ascii_num = |c: u8| (c - 48) as u16;
-
-        if let [a, b, c] = resp[9..12] {
-            let status = ascii_num(a) * 100 + ascii_num(b) * 10 + ascii_num(c);
-            let (server, body_length) = if status_only {
-                (None, None)
-            } else {
-                (parse_server(resp), body_length(resp))
-            };
-            Ok(Response {
-                status,
-                server,
-                body_length,
+/// Parses a response as it comes in, in pieces of any size. Finds out
+/// where the response ends, because otherwise the only end of a response
+/// is the server closing the connection.
+pub struct ResponseReader {
+    head_request: bool,
+    buf: Vec<u8>, // head until it is complete
+    head: Option<Head>,
+    body: Option<Framing>,
+    body_length: usize,
+    closed: bool,
+    keep_alive: bool,
+}
+
+enum Framing {
+    Length(usize), // remaining bytes
+    Chunked(Chunked),
+    UntilClose,
+}
+
+struct Chunked {
+    state: Chunk,
+    line: Vec<u8>, // incomplete line
+}
+
+#[derive(Debug, PartialEq)]
+enum Chunk {
+    Size,
+    Data(usize), // remaining bytes
+    DataEnd,     // CRLF after the data
+    Trailer,
+    Done,
+}
+
+impl ResponseReader {
+    pub fn new(head_request: bool) -> Self {
+        ResponseReader {
+            head_request,
+            buf: vec![],
+            head: None,
+            body: None,
+            body_length: 0,
+            closed: false,
+            keep_alive: false,
+        }
+    }
+
+    /// Starts over for the next response
+    pub fn reset(&mut self) {
+        *self = ResponseReader::new(self.head_request);
+    }
+
+    /// Reads the next bytes of the response. Returns how many of them are
+    /// part of the body, not counting the framing of chunks. Anything after
+    /// the end of the response is ignored.
+    pub fn feed(&mut self, data: &[u8]) -> Result<usize, String> {
+        let rest;
+        let mut data = data;
+        if self.head.is_none() {
+            let search_from = self.buf.len().saturating_sub(2);
+            self.buf.extend_from_slice(data);
+            match head_end(&self.buf, search_from) {
+                Some(end) => {
+                    rest = self.buf.split_off(end);
+                    data = &rest;
+                    let head = Head::parse(&mem::take(&mut self.buf))?;
+                    if head.status / 100 == 1 && head.status != 101 {
+                        // interim response, the actual one follows
+                        return self.feed(data);
+                    }
+                    self.body = Some(self.framing(&head)?);
+                    self.head = Some(head);
+                }
+                None if self.buf.len() > MAX_HEAD_SIZE => {
+                    return Err("Response head too large".into())
+                }
+                None => return Ok(0),
+            }
+        }
+
+        let body = match self.body.as_mut() {
+            Some(Framing::Length(remaining)) => {
+                let n = data.len().min(*remaining);
+                *remaining -= n;
+                n
+            }
+            Some(Framing::Chunked(chunked)) => chunked.feed(data)?,
+            Some(Framing::UntilClose) => data.len(),
+            None => 0,
+        };
+        self.body_length += body;
+        Ok(body)
+    }
+
+    /// Tells the reader that the server has closed the connection, which
+    /// ends a response without length.
+    pub fn close(&mut self) {
+        self.closed = true;
+    }
+
+    /// Whether the complete response has been read
+    pub fn is_complete(&self) -> bool {
+        match &self.body {
+            Some(Framing::Length(remaining)) => *remaining == 0,
+            Some(Framing::Chunked(chunked)) => chunked.state == Chunk::Done,
+            Some(Framing::UntilClose) => self.closed,
+            None => false,
+        }
+    }
+
+    /// Status line and headers, once they have been read
+    pub fn head(&self) -> Option<&Head> {
+        self.head.as_ref()
+    }
+
+    /// Length of the body read so far
+    pub fn body_length(&self) -> usize {
+        self.body_length
+    }
+
+    /// Whether the server allows sending another request on this connection
+    pub fn keep_alive(&self) -> bool {
+        self.keep_alive
+    }
+
+    fn framing(&mut self, head: &Head) -> Result<Framing, String> {
+        let framing = if self.head_request || head.status == 204 || head.status == 304 {
+            Framing::Length(0)
+        } else if head.is_chunked() {
+            Framing::Chunked(Chunked {
+                state: Chunk::Size,
+                line: vec![],
             })
+        } else if let Some(length) = head.content_length()? {
+            Framing::Length(length)
         } else {
-            Err(format!(
-                "Cannot parse as HTTP header: {}",
-                String::from_utf8_lossy(resp)
-            ))
-        }
+            Framing::UntilClose
+        };
+
+        self.keep_alive = !matches!(framing, Framing::UntilClose)
+            && !head.has_token("connection", "close")
+            && (head.version >= 1 || head.has_token("connection", "keep-alive"));
+
+        Ok(framing)
     }
 }
 
-fn parse_server(resp: &[u8]) -> Option<String> {
-    String::from_utf8_lossy(resp)
-        .split("\r\n")
-        .find_map(|line| {
-            // TODO this copies the string
-            if line.to_ascii_lowercase().starts_with("server:") {
-                Some(line[8..].to_owned())
-            } else {
-                None
+impl Chunked {
+    /// Returns how many bytes of chunk data there were
+    fn feed(&mut self, data: &[u8]) -> Result<usize, String> {
+        let mut body = 0;
+        let mut data = data;
+        while !data.is_empty() && self.state != Chunk::Done {
+            if let Chunk::Data(remaining) = self.state {
+                let n = remaining.min(data.len());
+                body += n;
+                data = &data[n..];
+                self.state = if n == remaining {
+                    Chunk::DataEnd
+                } else {
+                    Chunk::Data(remaining - n)
+                };
+                continue;
             }
-        })
+
+            match data.iter().position(|&b| b == b'\n') {
+                Some(end) => {
+                    self.line.extend_from_slice(&data[..end]);
+                    data = &data[end + 1..];
+                    let mut line = mem::take(&mut self.line);
+                    if line.last() == Some(&b'\r') {
+                        line.pop();
+                    }
+                    self.state = self.next_state(&line)?;
+                }
+                None => {
+                    self.line.extend_from_slice(data);
+                    data = &[];
+                    if self.line.len() > MAX_HEAD_SIZE {
+                        return Err("Chunk line too long".into());
+                    }
+                }
+            }
+        }
+        Ok(body)
+    }
+
+    fn next_state(&self, line: &[u8]) -> Result<Chunk, String> {
+        match self.state {
+            Chunk::Size => {
+                let line = String::from_utf8_lossy(line);
+                let size = line.split(';').next().unwrap_or_default().trim();
+                if size.is_empty() || !size.bytes().all(|b| b.is_ascii_hexdigit()) {
+                    return Err(format!("Invalid chunk size: {}", line));
+                }
+                match usize::from_str_radix(size, 16) {
+                    Ok(0) => Ok(Chunk::Trailer),
+                    Ok(size) => Ok(Chunk::Data(size)),
+                    Err(_) => Err(format!("Invalid chunk size: {}", line)),
+                }
+            }
+            Chunk::DataEnd if line.is_empty() => Ok(Chunk::Size),
+            Chunk::DataEnd => Err("Missing CRLF after chunk data".into()),
+            Chunk::Trailer if line.is_empty() => Ok(Chunk::Done),
+            Chunk::Trailer => Ok(Chunk::Trailer),
+            Chunk::Data(_) | Chunk::Done => unreachable!("not reading a line"),
+        }
+    }
 }
 
-fn body_length(resp: &[u8]) -> Option<usize> {
-    String::from_utf8_lossy(resp)
-        .split("\r\n\r\n")
-        .last()
-        .map(|b| b.len())
+/// Position right after the empty line that ends the head, looking for
+/// line feeds from the given position on.
+fn head_end(buf: &[u8], from: usize) -> Option<usize> {
+    (from..buf.len()).find_map(|i| match &buf[i..] {
+        [b'\n', b'\n', ..] => Some(i + 2),
+        [b'\n', b'\r', b'\n', ..] => Some(i + 3),
+        _ => None,
+    })
 }
 
-pub fn create_request(url: &Url, use_head: bool) -> String {
-    let host = url.host_str().expect("Missing host");
-    let path = &url[Position::BeforePath..];
-    let method = if use_head { "HEAD" } else { "GET" };
-    format!(
-        "{} {} HTTP/1.0\r\nHost: {}\r\n{}\r\n\r\n",
-        method, path, host, "Accept: */*"
-    )
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+}
+
+impl Method {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+        }
+    }
+}
+
+pub struct Request {
+    method: Method,
+    path: String,
+    keep_alive: bool,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    pub fn new(method: Method, url: &Url) -> Self {
+        let host = url.host_str().expect("Missing host");
+        let mut request = Request {
+            method,
+            path: url[Position::BeforePath..].to_owned(),
+            keep_alive: false,
+            headers: vec![],
+            body: vec![],
+        };
+        request.set_header("Host", host);
+        request.set_header("Accept", "*/*");
+        request
+    }
+
+    pub fn keep_alive(&mut self) {
+        self.keep_alive = true;
+        self.set_header("Connection", "keep-alive");
+    }
+
+    /// Sets a header, replacing any existing header with the same name
+    pub fn set_header(&mut self, name: &str, value: &str) {
+        match self
+            .headers
+            .iter_mut()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        {
+            Some(header) => *header = (name.to_owned(), value.to_owned()),
+            None => self.headers.push((name.to_owned(), value.to_owned())),
+        }
+    }
+
+    /// Adds the user's headers. They replace the ones set so far, e.g. the
+    /// default Host and Accept, but a header repeated by the user is sent
+    /// as often as it's given.
+    pub fn add_headers(&mut self, headers: &[(String, String)]) {
+        self.headers
+            .retain(|(n, _)| !headers.iter().any(|(name, _)| n.eq_ignore_ascii_case(name)));
+        self.headers.extend(headers.iter().cloned());
+    }
+
+    pub fn basic_auth(&mut self, credentials: &str) {
+        let encoded = base64::encode(credentials);
+        self.set_header("Authorization", &format!("Basic {}", encoded));
+    }
+
+    pub fn set_body(&mut self, body: Vec<u8>, content_type: &str) {
+        self.set_header("Content-Length", &body.len().to_string());
+        self.set_header("Content-Type", content_type);
+        self.body = body;
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let version = if self.keep_alive {
+            "HTTP/1.1"
+        } else {
+            "HTTP/1.0"
+        };
+        let mut head = format!("{} {} {}\r\n", self.method.as_str(), self.path, version);
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str("\r\n");
+
+        let mut bytes = head.into_bytes();
+        bytes.extend_from_slice(&self.body);
+        bytes
+    }
 }
 
 #[cfg(test)]
 mod test {
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+
     use super::*;
 
     #[test]
-    fn test_parse_status_code() {
-        assert_eq!(
-            200,
-            Response::parse("HTTP/1.1 200 OK".as_bytes(), true)
-                .unwrap()
-                .status
-        );
+    fn test_parse_status_line() {
+        assert_eq!(Ok((1, 200)), parse_status_line("HTTP/1.1 200 OK"));
+        assert_eq!(Ok((0, 404)), parse_status_line("HTTP/1.0 404 Not Found"));
+        assert_eq!(Ok((1, 204)), parse_status_line("HTTP/1.1 204"));
+        assert!(parse_status_line("HTTP/1.1 20").is_err());
+        assert!(parse_status_line("HTTP/1.1 2x0 OK").is_err());
+        assert!(parse_status_line("HTTP/1.1 2000 OK").is_err());
+        assert!(parse_status_line("HTTP/2 200 OK").is_err());
+        assert!(parse_status_line("").is_err());
     }
 
     #[test]
-    fn test_parse_server() {
+    fn test_parse_headers() {
         let google_response = "HTTP/1.1 200 OK\r\n\
           Date: Thu, 18 Mar 2021 19:24:37 GMT\r\n\
           P3P: CP=\"This is not a P3P policy! See g.co/p3phelp for more info.\"\r\n\
-          Server: gws\r\n\
-        ";
+          server: gws\r\n\
+          X-Folded: a\r\n \
+          b\r\n\r\n";
+        let head = Head::parse(google_response.as_bytes()).unwrap();
+        assert_eq!(200, head.status);
+        assert_eq!(Some("gws"), head.header("Server"));
+        assert_eq!(Some("Thu, 18 Mar 2021 19:24:37 GMT"), head.header("date"));
+        assert_eq!(Some("a b"), head.header("x-folded"));
+        assert_eq!(None, head.header("Content-Type"));
+
+        assert!(Head::parse(b"HTTP/1.1 200 OK\r\nno colon\r\n\r\n").is_err());
+        assert!(Head::parse(b"HTTP/1.1 200 OK\r\nBad Name: x\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn test_read_short_and_invalid_responses() {
+        let mut reader = ResponseReader::new(false);
+        assert_eq!(Ok(0), reader.feed(b"HTTP/1.1 2"));
+        assert!(reader.head().is_none());
+        assert_eq!(Ok(0), reader.feed(b"00 OK\r\n"));
+        assert_eq!(Ok(0), reader.feed(b"\r"));
+        assert_eq!(Ok(0), reader.feed(b"\n"));
+        assert_eq!(Some(200), reader.head().map(|h| h.status));
+
+        assert!(ResponseReader::new(false).feed(b"garbage\r\n\r\n").is_err());
+        assert!(ResponseReader::new(false)
+            .feed(b"HTTP/1.1 200 OK\r\nContent-Length: abc\r\n\r\n")
+            .is_err());
+        assert!(ResponseReader::new(false)
+            .feed(b"HTTP/1.1 200 OK\r\nContent-Length: 1\r\nContent-Length: 2\r\n\r\n")
+            .is_err());
+        assert!(ResponseReader::new(false)
+            .feed(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n")
+            .is_err());
+        assert!(ResponseReader::new(false)
+            .feed(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n1\r\nab\r\n")
+            .is_err());
+    }
+
+    #[test]
+    fn test_read_interim_response() {
+        let mut reader = ResponseReader::new(false);
+        let response = b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 201 Created\r\n\
+            Content-Length: 2\r\n\r\nok";
+        assert_eq!(Ok(2), reader.feed(response));
+        assert_eq!(Some(201), reader.head().map(|h| h.status));
+        assert!(reader.is_complete());
+    }
+
+    #[test]
+    fn test_request_to_bytes() {
+        let url = Url::parse("http://localhost:8080/foo?bar=baz").unwrap();
+        assert_eq!(
+            "GET /foo?bar=baz HTTP/1.0\r\nHost: localhost\r\nAccept: */*\r\n\r\n",
+            String::from_utf8(Request::new(Method::Get, &url).to_bytes()).unwrap()
+        );
+
+        let mut request = Request::new(Method::Head, &url);
+        request.keep_alive();
         assert_eq!(
-            Some("gws".to_owned()),
-            Response::parse(google_response.as_bytes(), false)
-                .unwrap()
-                .server
+            "HEAD /foo?bar=baz HTTP/1.1\r\nHost: localhost\r\nAccept: */*\r\n\
+             Connection: keep-alive\r\n\r\n",
+            String::from_utf8(request.to_bytes()).unwrap()
         );
+    }
 
-        let google_response_simple = "HTTP/1.1 200 OK\r\nServer: gws\r\n";
+    #[test]
+    fn test_request_headers() {
+        let url = Url::parse("http://localhost").unwrap();
+        let mut request = Request::new(Method::Get, &url);
+        request.basic_auth("user:pass");
+        request.set_header("host", "example.com");
+        request.set_header("X-Api-Key", "secret");
         assert_eq!(
-            Some("gws".to_owned()),
-            Response::parse(google_response_simple.as_bytes(), false)
-                .unwrap()
-                .server
+            "GET / HTTP/1.0\r\nhost: example.com\r\nAccept: */*\r\n\
+             Authorization: Basic dXNlcjpwYXNz\r\nX-Api-Key: secret\r\n\r\n",
+            String::from_utf8(request.to_bytes()).unwrap()
         );
+    }
 
-        let no_server_response =
-            "HTTP/1.1 404 Not Found\r\nContent-Type: application/json;charset=UTF-8\r\n";
+    #[test]
+    fn test_request_repeated_headers() {
+        let url = Url::parse("http://localhost").unwrap();
+        let mut request = Request::new(Method::Get, &url);
+        request.set_header("Cookie", "a=1");
+        request.add_headers(&[
+            ("Accept-Encoding".to_owned(), "gzip".to_owned()),
+            ("accept-encoding".to_owned(), "br".to_owned()),
+            ("Host".to_owned(), "example.com".to_owned()),
+            ("X-Trace".to_owned(), "1".to_owned()),
+            ("X-Trace".to_owned(), "2".to_owned()),
+        ]);
         assert_eq!(
-            None,
-            Response::parse(no_server_response.as_bytes(), false)
-                .unwrap()
-                .server
+            "GET / HTTP/1.0\r\nAccept: */*\r\nCookie: a=1\r\nAccept-Encoding: gzip\r\n\
+             accept-encoding: br\r\nHost: example.com\r\nX-Trace: 1\r\nX-Trace: 2\r\n\r\n",
+            String::from_utf8(request.to_bytes()).unwrap()
         );
+    }
 
+    #[test]
+    fn test_request_with_body() {
+        let url = Url::parse("http://localhost").unwrap();
+        let mut request = Request::new(Method::Post, &url);
+        request.set_body(b"{\"a\": 1}".to_vec(), "application/json");
         assert_eq!(
-            None,
-            Response::parse(google_response.as_bytes(), true)
-                .unwrap()
-                .server
+            "POST / HTTP/1.0\r\nHost: localhost\r\nAccept: */*\r\nContent-Length: 8\r\n\
+             Content-Type: application/json\r\n\r\n{\"a\": 1}",
+            String::from_utf8(request.to_bytes()).unwrap()
         );
     }
+
+    #[test]
+    fn test_read_content_length() {
+        let mut reader = ResponseReader::new(false);
+        assert_eq!(
+            Ok(0),
+            reader.feed(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n")
+        );
+        assert!(!reader.is_complete());
+        assert_eq!(Ok(3), reader.feed(b"\r\nhel"));
+        assert!(!reader.is_complete());
+        assert_eq!(Ok(2), reader.feed(b"lo\r\n"));
+        assert!(reader.is_complete());
+        assert!(reader.keep_alive());
+        assert_eq!(5, reader.body_length());
+    }
+
+    #[test]
+    fn test_read_chunked() {
+        let response = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+            5;ext=1\r\nhello\r\nA\r\n0123456789\r\n0\r\nTrailer: x\r\n\r\n";
+        for split in 0..response.len() {
+            let mut reader = ResponseReader::new(false);
+            reader.feed(&response[..split]).unwrap();
+            assert!(!reader.is_complete(), "complete after {} bytes", split);
+            reader.feed(&response[split..]).unwrap();
+            assert!(reader.is_complete(), "incomplete when split at {}", split);
+            assert_eq!(15, reader.body_length());
+        }
+    }
+
+    #[test]
+    fn test_read_until_close() {
+        let mut reader = ResponseReader::new(false);
+        assert_eq!(Ok(5), reader.feed(b"HTTP/1.0 200 OK\r\n\r\nhello"));
+        assert!(!reader.is_complete());
+        assert!(!reader.keep_alive());
+        reader.close();
+        assert!(reader.is_complete());
+    }
+
+    #[test]
+    fn test_read_head_response() {
+        let mut reader = ResponseReader::new(true);
+        reader
+            .feed(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n")
+            .unwrap();
+        assert!(reader.is_complete());
+    }
+
+    #[test]
+    fn test_read_keep_alive() {
+        let keep_alive = |head: &[u8]| {
+            let mut reader = ResponseReader::new(false);
+            reader.feed(head).unwrap();
+            reader.keep_alive()
+        };
+        assert!(keep_alive(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n"));
+        assert!(!keep_alive(
+            b"HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Length: 0\r\n\r\n"
+        ));
+        assert!(!keep_alive(b"HTTP/1.0 200 OK\r\nContent-Length: 0\r\n\r\n"));
+        assert!(keep_alive(
+            b"HTTP/1.0 200 OK\r\nConnection: Keep-Alive\r\nContent-Length: 0\r\n\r\n"
+        ));
+    }
+
+    /// Encodes the body with a Content-Length or in chunks of the given sizes
+    fn response(body: &[u8], chunk_sizes: Option<&[usize]>) -> Vec<u8> {
+        let mut response = b"HTTP/1.1 200 OK\r\nServer: test\r\n".to_vec();
+        match chunk_sizes {
+            None => {
+                response.extend_from_slice(
+                    format!("Content-Length: {}\r\n\r\n", body.len()).as_bytes(),
+                );
+                response.extend_from_slice(body);
+            }
+            Some(sizes) => {
+                response.extend_from_slice(b"Transfer-Encoding: chunked\r\n\r\n");
+                let mut rest = body;
+                for size in sizes.iter().cycle() {
+                    if rest.is_empty() {
+                        break;
+                    }
+                    let (chunk, next) = rest.split_at((*size).min(rest.len()));
+                    response.extend_from_slice(format!("{:x}\r\n", chunk.len()).as_bytes());
+                    response.extend_from_slice(chunk);
+                    response.extend_from_slice(b"\r\n");
+                    rest = next;
+                }
+                response.extend_from_slice(b"0\r\n\r\n");
+            }
+        }
+        response
+    }
+
+    proptest! {
+        #[test]
+        fn prop_read_response_split_anywhere(
+            body in vec(any::<u8>(), 0..300),
+            chunk_sizes in vec(1..50usize, 1..10),
+            chunked in any::<bool>(),
+            splits in vec(any::<usize>(), 0..10),
+        ) {
+            let response = response(&body, if chunked { Some(&chunk_sizes) } else { None });
+            let mut splits: Vec<usize> = splits.iter().map(|s| s % response.len()).collect();
+            splits.sort_unstable();
+            splits.push(response.len());
+
+            let mut reader = ResponseReader::new(false);
+            let mut body_bytes = 0;
+            let mut start = 0;
+            for end in splits {
+                prop_assert!(!reader.is_complete(), "complete after {} bytes", start);
+                body_bytes += reader.feed(&response[start..end]).unwrap();
+                start = end;
+            }
+
+            prop_assert!(reader.is_complete());
+            prop_assert!(reader.keep_alive());
+            prop_assert_eq!(Some("test"), reader.head().and_then(|h| h.header("server")));
+            prop_assert_eq!(body.len(), body_bytes);
+            prop_assert_eq!(body.len(), reader.body_length());
+        }
+
+        #[test]
+        fn prop_read_garbage_without_panic(data in vec(any::<u8>(), 0..200), split in any::<usize>()) {
+            let split = split % (data.len() + 1);
+            let mut reader = ResponseReader::new(false);
+            let _ = reader
+                .feed(&data[..split])
+                .and_then(|_| reader.feed(&data[split..]));
+        }
+    }
 }
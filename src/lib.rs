@@ -1,5 +1,13 @@
+pub mod assertion;
 pub mod benchmarking;
 pub mod connection;
 pub mod ctx;
 pub mod http;
+pub mod log;
+pub mod profile;
+pub mod progress;
+pub mod report;
 pub mod reporting;
+pub mod schedule;
+pub mod stats;
+pub mod tls;
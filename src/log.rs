@@ -0,0 +1,128 @@
+use std::fmt::Display;
+use std::time::{Duration, Instant};
+
+use mio::Token;
+
+/// Messages per second, beyond that they are only counted
+const MESSAGES_PER_SECOND: usize = 10;
+
+/// Dumps are cut off after this many bytes
+const MAX_DUMP_SIZE: usize = 2048;
+
+/// Prints troubleshooting info to stderr, more the higher the verbosity:
+/// 2 and above prints warnings, 3 and above response codes and 4 and above
+/// the requests and responses as they go over the wire.
+pub struct Log {
+    verbosity: u8,
+    window: Instant, // start of the current second
+    messages: usize, // printed in the current second
+    suppressed: usize,
+}
+
+impl Log {
+    pub fn new(verbosity: u8) -> Self {
+        Log {
+            verbosity,
+            window: Instant::now(),
+            messages: 0,
+            suppressed: 0,
+        }
+    }
+
+    pub fn warning<M: Display>(&mut self, message: M) {
+        if self.verbosity >= 2 && self.allow(Instant::now()) {
+            eprintln!("WARNING: {}", message);
+        }
+    }
+
+    pub fn response_code(&mut self, conn: Token, status: u16) {
+        if self.verbosity >= 3 && self.allow(Instant::now()) {
+            eprintln!("LOG: Response code = {} on connection {}", status, conn.0);
+        }
+    }
+
+    pub fn request_sent(&mut self, conn: Token, payload: &[u8]) {
+        if self.verbosity >= 4 && self.allow(Instant::now()) {
+            eprint!(
+                "{}",
+                dump(&format!("Request sent on connection {}", conn.0), payload)
+            );
+        }
+    }
+
+    pub fn data_received(&mut self, conn: Token, data: &[u8]) {
+        if self.verbosity >= 4 && self.allow(Instant::now()) {
+            eprint!(
+                "{}",
+                dump(&format!("Received on connection {}", conn.0), data)
+            );
+        }
+    }
+
+    /// Reports the messages that were not printed
+    pub fn finish(&mut self) {
+        if self.suppressed > 0 {
+            eprintln!("LOG: {} messages suppressed", self.suppressed);
+            self.suppressed = 0;
+        }
+    }
+
+    /// Whether another message may be printed now
+    fn allow(&mut self, now: Instant) -> bool {
+        if now - self.window >= Duration::from_secs(1) {
+            self.finish();
+            self.window = now;
+            self.messages = 0;
+        }
+        if self.messages < MESSAGES_PER_SECOND {
+            self.messages += 1;
+            true
+        } else {
+            self.suppressed += 1;
+            false
+        }
+    }
+}
+
+fn dump(title: &str, data: &[u8]) -> String {
+    let shown = &data[..data.len().min(MAX_DUMP_SIZE)];
+    let mut dump = format!(
+        "LOG: {} ({} bytes):\n---\n{}",
+        title,
+        data.len(),
+        String::from_utf8_lossy(shown)
+    );
+    if shown.len() < data.len() {
+        dump.push_str(&format!("\n... {} more bytes", data.len() - shown.len()));
+    }
+    dump.push_str("\n---\n");
+    dump
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allow_per_second() {
+        let mut log = Log::new(4);
+        let start = log.window;
+        let allowed = (0..15).filter(|_| log.allow(start)).count();
+        assert_eq!(MESSAGES_PER_SECOND, allowed);
+        assert_eq!(5, log.suppressed);
+
+        assert!(log.allow(start + Duration::from_secs(1)));
+        assert_eq!(0, log.suppressed);
+    }
+
+    #[test]
+    fn test_dump() {
+        assert_eq!(
+            "LOG: Sent (5 bytes):\n---\nhello\n---\n",
+            dump("Sent", b"hello")
+        );
+
+        let large = dump("Sent", &[b'a'; MAX_DUMP_SIZE + 10]);
+        assert!(large.ends_with("a\n... 10 more bytes\n---\n"));
+    }
+}
@@ -4,21 +4,38 @@ extern crate structopt;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::error::Error;
+use std::fs;
+use std::fs::File;
 use std::io;
+use std::io::{IsTerminal, Write};
 use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process;
 use std::rc::Rc;
 use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+use std::thread;
 use std::time::Duration;
 
 use structopt::StructOpt;
 use url::Url;
 
 use mio::net::TcpStream;
+use rab::assertion::Assertion;
 use rab::benchmarking::benchmark;
-use rab::connection::Connection;
-use rab::ctx::Ctx;
-use rab::http;
+use rab::connection::{Connection, Stream};
+use rab::ctx::{Budget, Ctx, Warmup};
+use rab::http::{Method, Request};
+use rab::log::Log;
+use rab::profile::{Profile, Shape};
+use rab::progress::{Format, Progress, Snapshot};
 use rab::reporting::Reporter;
+use rab::schedule::Schedule;
+use rab::tls;
+
+/// Exit code when an assertion fails, runtime errors exit with 1
+const ASSERTION_FAILED: i32 = 2;
 
 #[derive(StructOpt, Debug)]
 #[structopt(name = "rab", about = "A drop-in replacement ApacheBench")]
@@ -34,24 +51,247 @@ struct Opts {
     #[structopt(
         short = "n",
         long,
-        default_value = "1",
-        help = "Number of requests to perform"
+        help = "Number of requests to perform, 1 by default. With -t, the\n\
+                benchmark ends after whichever comes first."
     )]
-    requests: usize,
+    requests: Option<usize>,
 
-    #[structopt(short = "i", help = "Use HEAD instead of GET")]
+    #[structopt(
+        short = "i",
+        conflicts_with_all = &["postfile", "putfile"],
+        help = "Use HEAD instead of GET"
+    )]
     use_head: bool,
 
+    #[structopt(
+        short = "p",
+        conflicts_with = "putfile",
+        parse(from_os_str),
+        help = "File containing data to POST. Remember also to set -T"
+    )]
+    postfile: Option<PathBuf>,
+
+    #[structopt(
+        short = "u",
+        parse(from_os_str),
+        help = "File containing data to PUT. Remember also to set -T"
+    )]
+    putfile: Option<PathBuf>,
+
+    #[structopt(
+        short = "T",
+        default_value = "text/plain",
+        help = "Content-type header to use for POST/PUT data"
+    )]
+    content_type: String,
+
+    #[structopt(short = "k", help = "Use HTTP KeepAlive feature")]
+    keep_alive: bool,
+
+    #[structopt(
+        short = "l",
+        help = "Accept variable document length (use this for dynamic pages)"
+    )]
+    accept_variable_length: bool,
+
     #[structopt(
         short,
         long,
-        help = "Seconds to max. to spend on benchmarking\nThis implies -n 50000"
+        help = "Seconds to max. to spend on benchmarking. Without -n, sends\n\
+                requests until then. Requests in flight are waited for."
     )]
     timelimit: Option<u64>,
 
+    #[structopt(
+        short = "s",
+        default_value = "30",
+        help = "Seconds to max. wait for each response"
+    )]
+    timeout: u64,
+
+    #[structopt(
+        short = "r",
+        help = "Don't exit on socket receive errors. This is always the case,\n\
+                failed requests are counted and the benchmark goes on."
+    )]
+    _continue_on_errors: bool,
+
+    #[structopt(
+        long,
+        parse(try_from_str = parse_rate),
+        help = "Send requests at a fixed rate, e.g. 2000/s or 600/m, no matter\n\
+                how fast the server responds. Opens more connections as needed."
+    )]
+    rate: Option<f64>,
+
+    #[structopt(
+        long,
+        default_value = "1000",
+        help = "Most connections to open with --rate, at least the concurrency.\n\
+                Due requests wait for a connection beyond that."
+    )]
+    max_connections: usize,
+
+    #[structopt(
+        long,
+        parse(try_from_str = parse_duration),
+        conflicts_with = "step",
+        help = "Ramp up the concurrency, or the rate with --rate, linearly\n\
+                over this time, e.g. 30s or 2m. The report has a summary for\n\
+                each tenth of the ramp."
+    )]
+    ramp: Option<Duration>,
+
+    #[structopt(
+        long,
+        parse(try_from_str = parse_step),
+        help = "Increase the concurrency, or the rate with --rate, in steps,\n\
+                e.g. 50/30s adds 50 every 30 seconds. The report has a summary\n\
+                for each step."
+    )]
+    step: Option<(f64, Duration)>,
+
+    #[structopt(
+        long,
+        parse(try_from_str = parse_warmup),
+        help = "Warm up the server with this many requests, or for this long,\n\
+                e.g. 1000 or 30s, before the benchmark starts. The warm-up\n\
+                doesn't show in the results."
+    )]
+    warmup: Option<Warmup>,
+
+    #[structopt(
+        long,
+        default_value = "1",
+        help = "Number of threads to send requests from, each with its share\n\
+                of the connections. Use more if rab is slower than the server."
+    )]
+    threads: usize,
+
+    #[structopt(
+        long,
+        help = "Show the progress every second, or --interval: requests per\n\
+                second and latencies of the last interval, failed requests and\n\
+                connections"
+    )]
+    progress: bool,
+
+    #[structopt(
+        long,
+        parse(try_from_str = parse_duration),
+        help = "Interval of --progress and of the time series, 1s by default"
+    )]
+    interval: Option<Duration>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        conflicts_with = "interval-json",
+        help = "Write a time series to this CSV file, one row per interval with\n\
+                requests, failed requests, requests per second, p50, p90, p99\n\
+                and max latency and bytes received"
+    )]
+    interval_csv: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Write the time series as JSON lines to this file"
+    )]
+    interval_json: Option<PathBuf>,
+
+    #[structopt(
+        short = "H",
+        number_of_values = 1,
+        parse(try_from_str = parse_header),
+        help = "Add Arbitrary header line, eg. 'Accept-Encoding: gzip'\n\
+                Inserted after all normal header lines. (repeatable)"
+    )]
+    headers: Vec<(String, String)>,
+
+    #[structopt(
+        short = "C",
+        number_of_values = 1,
+        help = "Add cookie, eg. 'Apache=1234'. (repeatable)"
+    )]
+    cookies: Vec<String>,
+
+    #[structopt(
+        short = "A",
+        help = "Add Basic WWW Authentication, the attributes\n\
+                are a colon separated username and password."
+    )]
+    auth: Option<String>,
+
+    #[structopt(
+        long,
+        default_value = "3",
+        parse(try_from_str = parse_significant_digits),
+        help = "Precision of the recorded times (1-5)"
+    )]
+    significant_digits: u8,
+
+    #[structopt(
+        short = "e",
+        parse(from_os_str),
+        help = "Output CSV file with percentages served"
+    )]
+    csv_file: Option<PathBuf>,
+
+    #[structopt(
+        short = "g",
+        parse(from_os_str),
+        help = "Output collected data to gnuplot format file."
+    )]
+    gnuplot_file: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Write the distribution of response times in HdrHistogram's .hgrm format"
+    )]
+    hgrm: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        default_value = "text",
+        possible_values = &["text", "json"],
+        help = "Format of the report on stdout"
+    )]
+    output_format: OutputFormat,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Also write the report as JSON to this file"
+    )]
+    json_file: Option<PathBuf>,
+
+    #[structopt(
+        long = "assert",
+        number_of_values = 1,
+        help = "Check the results, e.g. 'p99<50ms', 'errors<0.1%' or 'rps>5000'.\n\
+                Times are pNN, mean, min and max, errors and non2xx are a count\n\
+                or a percentage. Exits with 2 if one fails. (repeatable)"
+    )]
+    assertions: Vec<Assertion>,
+
+    #[structopt(long, help = "Do not verify the certificate of the server")]
+    insecure: bool,
+
     #[structopt(help = "[http[s]://]hostname[:port]/path")]
     url: LenientUrl,
 
+    #[structopt(
+        short = "v",
+        default_value = "0",
+        help = "How much troubleshooting info to print\n\
+                4 and above prints the requests and responses,\n\
+                3 and above prints response codes (404, 200, etc.),\n\
+                2 and above prints warnings and info."
+    )]
+    verbosity: u8,
+
     #[structopt(
         short = "q",
         help = "Do not show progress when doing more than 150 requests"
@@ -77,57 +317,386 @@ impl FromStr for LenientUrl {
     }
 }
 
+#[derive(Debug, PartialEq)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!("unknown output format '{}'", s)),
+        }
+    }
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
-    let mut opt = Opts::from_args();
+    let opt = Opts::from_args();
+
+    let requests = match (opt.requests, opt.timelimit) {
+        (Some(requests), _) => requests,
+        (None, Some(_)) => usize::MAX, // until the time is up
+        (None, None) => 1,
+    };
 
-    if opt.concurrency > opt.requests {
-        panic!("Cannot use concurrency level greater than total number of requests");
+    if opt.concurrency > requests {
+        return Err("Cannot use concurrency level greater than total number of requests".into());
     }
 
-    if opt.timelimit.is_some() {
-        opt.requests = 50000;
+    if opt.threads == 0 || opt.threads > opt.concurrency {
+        return Err("Cannot use more threads than the concurrency level".into());
     }
 
-    let timelimit = Duration::from_secs(opt.timelimit.unwrap_or(u64::max_value()));
+    let timelimit = Duration::from_secs(opt.timelimit.unwrap_or(u64::MAX));
 
     let addr: SocketAddr = create_socket_addr(&opt.url.0)?;
 
-    let req = http::create_request(&opt.url.0, opt.use_head);
+    let req = create_request(&opt)?;
 
-    let heartbeatres = if opt.quiet || opt.requests <= 150 {
+    let json = opt.output_format == OutputFormat::Json;
+    // progress of a single one of several threads would be misleading
+    let heartbeatres = if opt.quiet
+        || opt.progress
+        || json
+        || opt.threads > 1
+        || requests <= 150
+        || requests == usize::MAX
+    {
         None
     } else {
-        Some(100.max(opt.requests / 10))
+        Some(100.max(requests / 10))
     };
-    let reporter = Rc::new(RefCell::new(Reporter::new(heartbeatres)));
-    let mut ctx = Ctx::new(req.into_bytes(), opt.requests, opt.concurrency)?;
+    let budget = Arc::new(Budget::new(requests));
+    let mut workers = (0..opt.threads)
+        .map(|thread| create_worker(&opt, &req, thread, heartbeatres, &budget))
+        .collect::<io::Result<Vec<_>>>()?;
 
-    let mut connections = HashMap::new();
+    let series = match (&opt.interval_csv, &opt.interval_json) {
+        (Some(csv), _) => Some((csv, Format::Csv)),
+        (_, Some(json)) => Some((json, Format::JsonLines)),
+        _ => None,
+    };
+    let progress = if opt.progress || series.is_some() {
+        let interval = opt.interval.unwrap_or(Duration::from_secs(1));
+        let (snapshots, receiver) = mpsc::channel();
+        for (_, reporter) in workers.iter_mut() {
+            reporter.show_progress(snapshots.clone(), interval);
+        }
 
-    for _ in 0..opt.concurrency {
-        let factory = Box::new(TcpStream::connect);
-        let connection = Connection::<TcpStream>::new(&mut ctx, addr, factory, reporter.clone())?;
-        connections.insert(connection.token, connection);
-    }
+        let mut progress = Progress::new(interval);
+        if opt.progress {
+            // stdout is for the report in json
+            let (out, tty): (Box<dyn Write>, bool) = if json {
+                (Box::new(io::stderr()), io::stderr().is_terminal())
+            } else {
+                (Box::new(io::stdout()), io::stdout().is_terminal())
+            };
+            progress.show_status(out, tty);
+        }
+        if let Some((file, format)) = series {
+            let out = Box::new(io::BufWriter::new(File::create(file)?));
+            progress.write_series(out, format)?;
+        }
+        Some((progress, receiver))
+    } else {
+        None
+    };
 
-    println!(
-        "Benchmarking {} (be patient)",
-        opt.url.0.host_str().unwrap()
-    );
-    println!();
+    if json {
+        eprintln!(
+            "Benchmarking {} (be patient)",
+            opt.url.0.host_str().unwrap()
+        );
+    } else {
+        println!(
+            "Benchmarking {} (be patient)",
+            opt.url.0.host_str().unwrap()
+        );
+        println!();
+    }
 
-    benchmark(timelimit, &mut ctx, &mut connections, reporter.clone())?;
+    let (ctx, reporter) = if opt.url.0.scheme() == "https" {
+        let connector = tls::connector(opt.url.0.host_str().unwrap(), opt.insecure)?;
+        run_threads(workers, addr, connector, timelimit, progress)?
+    } else {
+        run_threads(workers, addr, TcpStream::connect, timelimit, progress)?
+    };
 
     if heartbeatres.is_some() {
         println!("Finished {} requests", ctx.total_responses());
         println!();
     }
 
-    reporter.borrow().print(&opt.url.0, &ctx);
+    let mut report = reporter.report(&opt.url.0, &ctx);
+    let total = &reporter.latencies().total;
+    report.assertions = opt
+        .assertions
+        .iter()
+        .map(|assertion| assertion.check(&report, total))
+        .collect();
+    match opt.output_format {
+        OutputFormat::Text => report.print(),
+        OutputFormat::Json => {
+            report.write_json(io::stdout())?;
+            println!();
+        }
+    }
+
+    if let Some(json_file) = &opt.json_file {
+        report.write_json(File::create(json_file)?)?;
+    }
+
+    if let Some(csv_file) = &opt.csv_file {
+        reporter.write_csv_percentiles(&mut File::create(csv_file)?)?;
+    }
+
+    if let Some(gnuplot_file) = &opt.gnuplot_file {
+        reporter.write_gnuplot(&mut File::create(gnuplot_file)?)?;
+    }
+
+    if let Some(hgrm) = &opt.hgrm {
+        reporter.write_hgrm(&mut File::create(hgrm)?)?;
+    }
+
+    if !report.passed() {
+        io::stdout().flush()?;
+        process::exit(ASSERTION_FAILED);
+    }
 
     Ok(())
 }
 
+/// Context and reporter for one of the threads, which has its share of the
+/// connections and, in proportion, of the rate. Several threads share the
+/// requests.
+fn create_worker(
+    opt: &Opts,
+    req: &Request,
+    thread: usize,
+    heartbeatres: Option<usize>,
+    budget: &Arc<Budget>,
+) -> io::Result<(Ctx, Reporter)> {
+    let threads = opt.threads;
+    let share = |total: usize| total / threads + usize::from(thread < total % threads);
+
+    let mut reporter = Reporter::new(heartbeatres, opt.significant_digits);
+    if opt.gnuplot_file.is_some() {
+        reporter.collect_requests();
+    }
+
+    let concurrency = share(opt.concurrency);
+    let part = |total: f64| total * concurrency as f64 / opt.concurrency as f64;
+
+    let mut ctx = Ctx::new(req.to_bytes(), budget.max(), concurrency)?;
+    ctx.keep_alive = opt.keep_alive;
+    ctx.head_request = opt.use_head;
+    ctx.posting = opt.postfile.is_some() || opt.putfile.is_some();
+    ctx.accept_variable_length = opt.accept_variable_length;
+    ctx.timeout = Duration::from_secs(opt.timeout);
+    ctx.log = Log::new(opt.verbosity);
+    ctx.schedule = opt.rate.map(|rate| Schedule::new(part(rate)));
+    ctx.max_connections = share(opt.max_connections.max(opt.concurrency));
+    ctx.warmup = opt.warmup.map(|warmup| match warmup {
+        Warmup::Requests(requests) => Warmup::Requests(share(requests)),
+        duration => duration,
+    });
+    let shape = match (opt.ramp, opt.step) {
+        (Some(duration), _) => Some(Shape::Ramp(duration)),
+        (_, Some((increment, interval))) => Some(Shape::Steps(part(increment), interval)),
+        _ => None,
+    };
+    ctx.profile = shape.map(|shape| {
+        let target = ctx
+            .schedule
+            .as_ref()
+            .map_or(ctx.concurrency as f64, |s| s.rate());
+        Profile::new(shape, target)
+    });
+    if threads > 1 {
+        ctx.budget = Some(budget.clone());
+    }
+    Ok((ctx, reporter))
+}
+
+/// Runs each worker on its own thread and adds up their results. Shows the
+/// progress meanwhile, if asked to.
+fn run_threads<S, F>(
+    workers: Vec<(Ctx, Reporter)>,
+    addr: SocketAddr,
+    factory: F,
+    timelimit: Duration,
+    progress: Option<(Progress, Receiver<Snapshot>)>,
+) -> io::Result<(Ctx, Reporter)>
+where
+    S: Stream,
+    F: Fn(SocketAddr) -> io::Result<S> + Clone + Send + 'static,
+{
+    let handles: Vec<_> = workers
+        .into_iter()
+        .map(|(mut ctx, reporter)| {
+            let factory = factory.clone();
+            thread::spawn(move || -> io::Result<(Ctx, Reporter)> {
+                let reporter = Rc::new(RefCell::new(reporter));
+                run(&mut ctx, addr, factory, reporter.clone(), timelimit)?;
+                let reporter = Rc::try_unwrap(reporter)
+                    .ok()
+                    .expect("Connections must be closed");
+                Ok((ctx, reporter.into_inner()))
+            })
+        })
+        .collect();
+
+    let shown = match progress {
+        Some((progress, snapshots)) => progress.run(snapshots),
+        None => Ok(()),
+    };
+
+    // every thread is done before an error of one of them is returned
+    let results: Vec<_> = handles
+        .into_iter()
+        .map(|handle| handle.join().expect("Benchmark thread panicked"))
+        .collect();
+    shown?;
+    let mut results = results.into_iter();
+    let (mut ctx, mut reporter) = results.next().expect("No benchmark thread")?;
+    for result in results {
+        let (other_ctx, other_reporter) = result?;
+        ctx.merge(other_ctx);
+        reporter.merge(other_reporter);
+    }
+    Ok((ctx, reporter))
+}
+
+fn run<S, F>(
+    ctx: &mut Ctx,
+    addr: SocketAddr,
+    factory: F,
+    reporter: Rc<RefCell<Reporter>>,
+    timelimit: Duration,
+) -> io::Result<()>
+where
+    S: Stream,
+    F: Fn(SocketAddr) -> io::Result<S> + Clone + 'static,
+{
+    let mut connections = HashMap::new();
+
+    // with a profile, the benchmark opens more connections as it ramps up
+    let initial = match &ctx.profile {
+        Some(profile) if ctx.schedule.is_none() => profile.level(Duration::ZERO) as usize,
+        _ => ctx.concurrency,
+    };
+    for _ in 0..initial {
+        let factory = Box::new(factory.clone());
+        let connection = Connection::new(ctx, addr, factory, reporter.clone())?;
+        connections.insert(connection.token, connection);
+    }
+
+    benchmark(timelimit, ctx, &mut connections, reporter)
+}
+
+fn create_request(opt: &Opts) -> io::Result<Request> {
+    let (method, bodyfile) = match (&opt.postfile, &opt.putfile) {
+        (Some(postfile), _) => (Method::Post, Some(postfile)),
+        (_, Some(putfile)) => (Method::Put, Some(putfile)),
+        _ if opt.use_head => (Method::Head, None),
+        _ => (Method::Get, None),
+    };
+
+    let mut req = Request::new(method, &opt.url.0);
+    if opt.keep_alive {
+        req.keep_alive();
+    }
+    if let Some(bodyfile) = bodyfile {
+        req.set_body(fs::read(bodyfile)?, &opt.content_type);
+    }
+    if let Some(auth) = &opt.auth {
+        req.basic_auth(auth);
+    }
+    if !opt.cookies.is_empty() {
+        req.set_header("Cookie", &opt.cookies.join("; "));
+    }
+    req.add_headers(&opt.headers);
+    Ok(req)
+}
+
+fn parse_significant_digits(s: &str) -> Result<u8, String> {
+    match s.parse() {
+        Ok(digits) if (1..=5).contains(&digits) => Ok(digits),
+        _ => Err("must be between 1 and 5".to_string()),
+    }
+}
+
+/// Requests per second from e.g. 2000/s or 600/m
+fn parse_rate(s: &str) -> Result<f64, String> {
+    let (n, per_secs) = match s.split_once('/') {
+        None => (s, 1.0),
+        Some((n, "s")) => (n, 1.0),
+        Some((n, "m")) => (n, 60.0),
+        _ => return Err(format!("invalid rate '{}', expected e.g. 2000/s", s)),
+    };
+    match n.parse::<f64>() {
+        Ok(n) if n > 0.0 && n.is_finite() => Ok(n / per_secs),
+        _ => Err(format!("invalid rate '{}', expected e.g. 2000/s", s)),
+    }
+}
+
+/// Duration from e.g. 500ms, 30s or 2m
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let (n, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => s.split_at(i),
+        None => (s, "s"),
+    };
+    let n: u64 = n
+        .parse()
+        .map_err(|_| format!("invalid duration '{}', expected e.g. 30s", s))?;
+    match unit {
+        "ms" => Ok(Duration::from_millis(n)),
+        "s" => Ok(Duration::from_secs(n)),
+        "m" => Ok(Duration::from_secs(n * 60)),
+        _ => Err(format!("invalid duration '{}', expected e.g. 30s", s)),
+    }
+    .and_then(|d| {
+        if d.is_zero() {
+            Err(format!("invalid duration '{}', must not be zero", s))
+        } else {
+            Ok(d)
+        }
+    })
+}
+
+/// Increment and interval from e.g. 50/30s
+fn parse_step(s: &str) -> Result<(f64, Duration), String> {
+    let (n, interval) = s
+        .split_once('/')
+        .ok_or_else(|| format!("invalid step '{}', expected e.g. 50/30s", s))?;
+    match n.parse::<f64>() {
+        Ok(n) if n > 0.0 && n.is_finite() => Ok((n, parse_duration(interval)?)),
+        _ => Err(format!("invalid step '{}', expected e.g. 50/30s", s)),
+    }
+}
+
+/// Number of requests, e.g. 1000, or a duration, e.g. 30s
+fn parse_warmup(s: &str) -> Result<Warmup, String> {
+    match s.parse() {
+        Ok(requests) => Ok(Warmup::Requests(requests)),
+        Err(_) => parse_duration(s).map(Warmup::Duration),
+    }
+}
+
+fn parse_header(s: &str) -> Result<(String, String), String> {
+    match s.split_once(':') {
+        Some((name, value)) if !name.trim().is_empty() => {
+            Ok((name.trim().to_owned(), value.trim().to_owned()))
+        }
+        _ => Err(format!("invalid header '{}', expected 'Name: value'", s)),
+    }
+}
+
 fn create_socket_addr(url: &Url) -> io::Result<SocketAddr> {
     url.socket_addrs(|| url.port_or_known_default())
         .map(|ss| ss[0])
@@ -184,6 +753,61 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_parse_rate() {
+        assert_eq!(Ok(2000.0), parse_rate("2000/s"));
+        assert_eq!(Ok(2000.0), parse_rate("2000"));
+        assert_eq!(Ok(10.0), parse_rate("600/m"));
+        assert_eq!(Ok(0.5), parse_rate("0.5"));
+        assert!(parse_rate("0/s").is_err());
+        assert!(parse_rate("100/h").is_err());
+        assert!(parse_rate("fast").is_err());
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(Ok(Duration::from_millis(500)), parse_duration("500ms"));
+        assert_eq!(Ok(Duration::from_secs(30)), parse_duration("30s"));
+        assert_eq!(Ok(Duration::from_secs(30)), parse_duration("30"));
+        assert_eq!(Ok(Duration::from_secs(120)), parse_duration("2m"));
+        assert!(parse_duration("0s").is_err());
+        assert!(parse_duration("1h").is_err());
+        assert!(parse_duration("s").is_err());
+    }
+
+    #[test]
+    fn test_parse_step() {
+        assert_eq!(Ok((50.0, Duration::from_secs(30))), parse_step("50/30s"));
+        assert!(parse_step("50").is_err());
+        assert!(parse_step("0/30s").is_err());
+        assert!(parse_step("50/fast").is_err());
+    }
+
+    #[test]
+    fn test_parse_warmup() {
+        assert_eq!(Ok(Warmup::Requests(1000)), parse_warmup("1000"));
+        assert_eq!(
+            Ok(Warmup::Duration(Duration::from_secs(30))),
+            parse_warmup("30s")
+        );
+        assert!(parse_warmup("-1").is_err());
+        assert!(parse_warmup("soon").is_err());
+    }
+
+    #[test]
+    fn test_parse_header() {
+        assert_eq!(
+            ("X-Api-Key".to_owned(), "a:b".to_owned()),
+            parse_header("X-Api-Key: a:b").unwrap()
+        );
+        assert_eq!(
+            ("Accept".to_owned(), String::new()),
+            parse_header("Accept:").unwrap()
+        );
+        assert!(parse_header("X-Api-Key").is_err());
+        assert!(parse_header(": foo").is_err());
+    }
+
     fn parse_url(url: &str) -> LenientUrl {
         LenientUrl::from_str(url).unwrap()
     }
@@ -0,0 +1,134 @@
+use std::time::Duration;
+
+/// Number of steps a linear ramp is summarized in
+const RAMP_STEPS: usize = 10;
+
+/// How the load grows over time up to the target, i.e. the concurrency or,
+/// with a fixed rate, the request rate.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shape {
+    /// Grows linearly, reaching the target after this duration
+    Ramp(Duration),
+    /// Grows by this much after each interval
+    Steps(f64, Duration),
+}
+
+#[derive(Debug)]
+pub struct Profile {
+    shape: Shape,
+    target: f64,
+}
+
+impl Profile {
+    pub fn new(shape: Shape, target: f64) -> Self {
+        Profile { shape, target }
+    }
+
+    /// The load after the time elapsed since the start, at least 1
+    pub fn level(&self, elapsed: Duration) -> f64 {
+        let level = match self.shape {
+            Shape::Ramp(duration) => {
+                let ramped = (elapsed.as_secs_f64() / duration.as_secs_f64()).min(1.0);
+                (self.target * ramped).floor()
+            }
+            Shape::Steps(..) => self.step_level(self.step(elapsed)),
+        };
+        level.max(1.0)
+    }
+
+    /// Index of the step at this time. The last step holds the target
+    /// until the end.
+    pub fn step(&self, elapsed: Duration) -> usize {
+        let (interval, last) = self.steps();
+        let step = (elapsed.as_secs_f64() / interval.as_secs_f64()) as usize;
+        step.min(last)
+    }
+
+    /// The load at the end of a step
+    pub fn step_level(&self, step: usize) -> f64 {
+        let level = match self.shape {
+            Shape::Ramp(_) => self.target * (step + 1) as f64 / RAMP_STEPS as f64,
+            Shape::Steps(increment, _) => increment * (step + 1) as f64,
+        };
+        level.min(self.target).max(1.0)
+    }
+
+    /// Time since the start when the next step begins, if there is one
+    pub fn next_step(&self, elapsed: Duration) -> Option<Duration> {
+        let (interval, last) = self.steps();
+        let step = self.step(elapsed);
+        if step < last {
+            Some(interval * (step + 1) as u32)
+        } else {
+            None
+        }
+    }
+
+    /// Time since the start when the level changes next, if it does
+    pub fn next_change(&self, elapsed: Duration) -> Option<Duration> {
+        let next_step = self.next_step(elapsed);
+        match self.shape {
+            Shape::Ramp(duration) if self.level(elapsed) < self.target => {
+                let next_level = self.level(elapsed) + 1.0;
+                let next_change = duration.mul_f64(next_level.min(self.target) / self.target);
+                Some(next_step.map_or(next_change, |step| step.min(next_change)))
+            }
+            _ => next_step,
+        }
+    }
+
+    /// Interval of the steps and index of the last one
+    fn steps(&self) -> (Duration, usize) {
+        match self.shape {
+            Shape::Ramp(duration) => (duration / RAMP_STEPS as u32, RAMP_STEPS),
+            Shape::Steps(increment, interval) => {
+                let steps = (self.target / increment).ceil() as usize;
+                (interval, steps.max(1) - 1)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn s(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn test_ramp() {
+        let profile = Profile::new(Shape::Ramp(s(10)), 100.0);
+
+        assert_eq!(1.0, profile.level(s(0)));
+        assert_eq!(50.0, profile.level(s(5)));
+        assert_eq!(100.0, profile.level(s(20)));
+
+        assert_eq!(0, profile.step(s(0)));
+        assert_eq!(5, profile.step(s(5)));
+        assert_eq!(10, profile.step(s(20)));
+        assert_eq!(60.0, profile.step_level(5));
+        assert_eq!(100.0, profile.step_level(10));
+
+        assert_eq!(Some(s(6)), profile.next_step(s(5)));
+        assert_eq!(None, profile.next_step(s(10)));
+
+        assert_eq!(Some(Duration::from_millis(5100)), profile.next_change(s(5)));
+        assert_eq!(None, profile.next_change(s(10)));
+    }
+
+    #[test]
+    fn test_steps() {
+        let profile = Profile::new(Shape::Steps(50.0, s(30)), 120.0);
+
+        assert_eq!(50.0, profile.level(s(0)));
+        assert_eq!(100.0, profile.level(s(30)));
+        assert_eq!(120.0, profile.level(s(60)));
+        assert_eq!(120.0, profile.level(s(600)));
+
+        assert_eq!(2, profile.step(s(600)));
+        assert_eq!(Some(s(60)), profile.next_step(s(45)));
+        assert_eq!(None, profile.next_step(s(60)));
+    }
+}
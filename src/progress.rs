@@ -0,0 +1,288 @@
+use std::io;
+use std::io::Write;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use hdrhistogram::Histogram;
+use serde::Serialize;
+
+use crate::stats;
+
+/// What a thread has done since its last snapshot
+pub struct Snapshot {
+    pub end: SystemTime,
+    pub window: Duration,
+    pub requests: usize,
+    pub failed: usize,
+    pub connections: usize, // open at the end
+    pub bytes: usize,       // received
+    pub total: Histogram<u64>,
+}
+
+/// How the time series is written
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Csv,
+    JsonLines,
+}
+
+/// A row of the time series, what all threads have done in one interval.
+/// Times are in milliseconds.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Row {
+    pub timestamp: f64, // end of the interval, seconds since the epoch
+    pub requests: usize,
+    pub failed: usize,
+    pub requests_per_sec: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub max: f64,
+    pub bytes: usize,
+}
+
+/// Collects the snapshots of all threads at each interval to show a status
+/// line, on a terminal updated in place and otherwise line by line, and to
+/// write a time series.
+pub struct Progress {
+    interval: Duration,
+    status: Option<(Box<dyn Write>, bool)>, // whether it's a terminal
+    series: Option<(Box<dyn Write>, Format)>,
+    started: Instant,
+    failed: usize, // so far
+}
+
+impl Progress {
+    pub fn new(interval: Duration) -> Self {
+        Progress {
+            interval,
+            status: None,
+            series: None,
+            started: Instant::now(),
+            failed: 0,
+        }
+    }
+
+    pub fn show_status(&mut self, out: Box<dyn Write>, tty: bool) {
+        self.status = Some((out, tty));
+    }
+
+    pub fn write_series(&mut self, mut out: Box<dyn Write>, format: Format) -> io::Result<()> {
+        if format == Format::Csv {
+            writeln!(
+                out,
+                "timestamp,requests,failed,requests_per_sec,p50,p90,p99,max,bytes"
+            )?;
+        }
+        self.series = Some((out, format));
+        Ok(())
+    }
+
+    /// Takes the snapshots until all threads are done
+    pub fn run(mut self, snapshots: Receiver<Snapshot>) -> io::Result<()> {
+        // half an interval after the threads send their snapshots
+        let mut next = Instant::now() + self.interval + self.interval / 2;
+        let mut pending = vec![];
+        loop {
+            match snapshots.recv_timeout(next.saturating_duration_since(Instant::now())) {
+                Ok(snapshot) => pending.push(snapshot),
+                Err(RecvTimeoutError::Timeout) => {
+                    self.interval_done(&pending)?;
+                    pending.clear();
+                    next += self.interval;
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+        if !pending.is_empty() {
+            self.interval_done(&pending)?;
+        }
+        if let Some((out, true)) = &mut self.status {
+            writeln!(out)?;
+        }
+        if let Some((out, _)) = &mut self.series {
+            out.flush()?;
+        }
+        Ok(())
+    }
+
+    fn interval_done(&mut self, snapshots: &[Snapshot]) -> io::Result<()> {
+        let (row, connections) = row(snapshots);
+        self.failed += row.failed;
+
+        if let Some((out, tty)) = &mut self.status {
+            let line = format!(
+                "[{:>5}s] {:.1} req/s, p50 {:.3} ms, p99 {:.3} ms, {} failed, {} connections",
+                self.started.elapsed().as_secs(),
+                row.requests_per_sec,
+                row.p50,
+                row.p99,
+                self.failed,
+                connections
+            );
+            if *tty {
+                // back to the start of the line and clear it
+                write!(out, "\r\x1b[K{}", line)?;
+            } else {
+                writeln!(out, "{}", line)?;
+            }
+            out.flush()?;
+        }
+
+        match &mut self.series {
+            Some((out, format)) => write_row(out, &row, *format),
+            None => Ok(()),
+        }
+    }
+}
+
+fn write_row<W: Write + ?Sized>(out: &mut W, row: &Row, format: Format) -> io::Result<()> {
+    match format {
+        Format::Csv => writeln!(
+            out,
+            "{:.3},{},{},{:.3},{:.3},{:.3},{:.3},{:.3},{}",
+            row.timestamp,
+            row.requests,
+            row.failed,
+            row.requests_per_sec,
+            row.p50,
+            row.p90,
+            row.p99,
+            row.max,
+            row.bytes
+        ),
+        Format::JsonLines => {
+            serde_json::to_writer(&mut *out, row)?;
+            writeln!(out)
+        }
+    }
+}
+
+/// Adds up the snapshots of one interval. Returns the open connections, too.
+fn row(snapshots: &[Snapshot]) -> (Row, usize) {
+    let mut total: Option<Histogram<u64>> = None;
+    let mut start: Option<SystemTime> = None;
+    let mut end = UNIX_EPOCH;
+    let mut row = Row {
+        timestamp: 0.0,
+        requests: 0,
+        failed: 0,
+        requests_per_sec: 0.0,
+        p50: 0.0,
+        p90: 0.0,
+        p99: 0.0,
+        max: 0.0,
+        bytes: 0,
+    };
+    let mut connections = 0;
+    for snapshot in snapshots {
+        let started = snapshot.end - snapshot.window;
+        start = Some(start.map_or(started, |start| start.min(started)));
+        end = end.max(snapshot.end);
+        row.requests += snapshot.requests;
+        row.failed += snapshot.failed;
+        row.bytes += snapshot.bytes;
+        connections += snapshot.connections;
+        match &mut total {
+            Some(total) => total
+                .add(&snapshot.total)
+                .expect("Histograms must have the same bounds"),
+            None => total = Some(snapshot.total.clone()),
+        }
+    }
+
+    // usually, each thread sends one snapshot of the same interval
+    let secs = start.map_or(0.0, |start| {
+        end.duration_since(start).unwrap_or_default().as_secs_f64()
+    });
+    if secs > 0.0 {
+        row.requests_per_sec = row.requests as f64 / secs;
+    }
+    row.timestamp = end
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64();
+    if let Some(total) = total.filter(|total| !total.is_empty()) {
+        row.p50 = stats::median(&total);
+        row.p90 = stats::percentile(&total, 90.0);
+        row.p99 = stats::percentile(&total, 99.0);
+        row.max = stats::max(&total);
+    }
+    (row, connections)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(failed: usize, requests: usize, time: u64) -> Snapshot {
+        let mut total = stats::histogram(3);
+        for _ in 0..requests {
+            total.record(time).unwrap();
+        }
+        Snapshot {
+            end: UNIX_EPOCH + Duration::from_secs(10),
+            window: Duration::from_millis(500),
+            requests,
+            failed,
+            connections: 2,
+            bytes: 100,
+            total,
+        }
+    }
+
+    #[test]
+    fn test_row() {
+        let (row, connections) = row(&[snapshot(0, 10, 1000), snapshot(1, 5, 2000)]);
+        assert_eq!(
+            Row {
+                timestamp: 10.0,
+                requests: 15,
+                failed: 1,
+                requests_per_sec: 30.0,
+                p50: 1.0,
+                p90: 2.0,
+                p99: 2.0,
+                max: 2.0,
+                bytes: 200,
+            },
+            row
+        );
+        assert_eq!(4, connections);
+    }
+
+    #[test]
+    fn test_row_of_one_thread() {
+        // the regular snapshot and the last one when the benchmark is done
+        let mut last = snapshot(0, 5, 1000);
+        last.end += Duration::from_millis(500);
+        let (row, _) = row(&[snapshot(0, 10, 1000), last]);
+        assert_eq!(15.0, row.requests_per_sec);
+    }
+
+    #[test]
+    fn test_row_without_snapshots() {
+        let (row, connections) = row(&[]);
+        assert_eq!(0, row.requests);
+        assert_eq!(0.0, row.p99);
+        assert_eq!(0, connections);
+    }
+
+    #[test]
+    fn test_write_row() {
+        let (row, _) = row(&[snapshot(0, 2, 1000)]);
+
+        let mut csv = vec![];
+        write_row(&mut csv, &row, Format::Csv).unwrap();
+        assert_eq!(
+            "10.000,2,0,4.000,1.000,1.000,1.000,1.000,100\n",
+            String::from_utf8(csv).unwrap()
+        );
+
+        let mut json = vec![];
+        write_row(&mut json, &row, Format::JsonLines).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(2, json["requests"]);
+        assert_eq!(1.0, json["p99"]);
+    }
+}
@@ -0,0 +1,432 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::io::Write;
+
+use serde::Serialize;
+
+/// Version of the report structure, incremented on incompatible changes
+pub const VERSION: u32 = 2;
+
+/// Results of a benchmark. All times are in milliseconds unless the name
+/// says otherwise.
+#[derive(Serialize, Debug)]
+pub struct Report {
+    pub version: u32,
+    pub config: Config,
+    pub server: Server,
+    pub counters: Counters,
+    pub throughput: Throughput,
+    pub rate: Option<Rate>,
+    pub connection_times: Option<ConnectionTimes>,
+    pub percentiles: Vec<Percentile>,
+    pub steps: Vec<Step>, // with a load profile
+    pub assertions: Vec<Assertion>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Config {
+    pub url: String,
+    pub concurrency: usize,
+    pub requests: Option<usize>, // none until the time limit
+    pub keep_alive: bool,
+    pub posting: bool,
+    pub accept_variable_length: bool,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Server {
+    pub software: String,
+    pub hostname: String,
+    pub port: u16,
+    pub tls_protocol: Option<String>,
+    pub document_path: String,
+    pub document_length: Option<usize>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Counters {
+    pub sent: usize,
+    pub complete: usize,
+    pub successful: usize,
+    pub unsuccessful: usize, // non-2xx
+    pub failed: usize,
+    pub failures: Failures,
+    pub errors: BTreeMap<String, usize>, // failures by what went wrong
+    pub keep_alive: usize,
+    pub status_codes: BTreeMap<u16, usize>,
+    pub status_classes: BTreeMap<String, usize>, // e.g. 2xx
+}
+
+/// Failed requests by reason
+#[derive(Serialize, Debug)]
+pub struct Failures {
+    pub connect: usize,
+    pub receive: usize,
+    pub length: usize, // body differs from the first response
+    pub exceptions: usize,
+}
+
+/// How well a fixed request rate was kept up
+#[derive(Serialize, Debug)]
+pub struct Rate {
+    pub target_per_sec: f64,
+    pub late: usize, // sent after the next request was already due
+    pub connections: usize,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Throughput {
+    pub time_taken_secs: f64,
+    pub bytes_sent: usize,
+    pub bytes_received: usize,
+    pub body_bytes_received: usize,
+    pub requests_per_sec: f64,
+    pub time_per_request: f64,
+    pub time_per_request_all_concurrent: f64,
+    pub kbytes_received_per_sec: f64,
+    pub kbytes_sent_per_sec: f64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ConnectionTimes {
+    pub connect: TimeStats,
+    pub processing: TimeStats,
+    pub waiting: TimeStats,
+    pub total: TimeStats,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TimeStats {
+    pub min: f64,
+    pub mean: f64,
+    pub std_dev: f64,
+    pub median: f64,
+    pub max: f64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Percentile {
+    pub percentage: u32,
+    pub time: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub corrected: Option<f64>, // for coordinated omission, with a fixed rate
+}
+
+/// Summary of a step of a load profile
+#[derive(Serialize, Debug)]
+pub struct Step {
+    pub step: usize,
+    pub level: f64, // concurrency or, with a fixed rate, requests per second
+    pub start_secs: f64,
+    pub duration_secs: f64,
+    pub requests: usize,
+    pub requests_per_sec: f64,
+    pub mean: f64,
+    pub p50: f64,
+    pub p99: f64,
+    pub failed: usize,
+}
+
+/// Outcome of an assertion on the results, e.g. p99<50ms
+#[derive(Serialize, Debug)]
+pub struct Assertion {
+    pub assertion: String,
+    pub actual: String, // with the unit, e.g. 12.5ms, or none
+    pub passed: bool,
+}
+
+impl Report {
+    pub fn write_json<W: Write>(&self, out: W) -> io::Result<()> {
+        serde_json::to_writer_pretty(out, self)?;
+        Ok(())
+    }
+
+    /// Prints the report like ab does
+    pub fn print(&self) {
+        let server = &self.server;
+        println!("Server Software:\t{}", server.software);
+        println!("Server Hostname:\t{}", server.hostname);
+        println!("Server Port:\t\t{}", server.port);
+        if let Some(protocol) = &server.tls_protocol {
+            println!("SSL/TLS Protocol:\t{}", protocol);
+            println!("TLS Server Name:\t{}", server.hostname);
+        }
+        println!();
+
+        println!("Document Path:\t\t{}", server.document_path);
+        if self.config.accept_variable_length {
+            println!("Document Length:\tVariable");
+        } else if let Some(doclen) = server.document_length {
+            println!("Document Length:\t{} bytes", doclen);
+        }
+        println!();
+
+        let counters = &self.counters;
+        println!("Concurrency Level:\t{}", self.config.concurrency);
+        println!(
+            "Time taken for tests:\t{:.3} seconds",
+            self.throughput.time_taken_secs
+        );
+        println!("Complete requests:\t{}", counters.complete);
+        println!("Failed requests:\t{}", counters.failed);
+        if counters.failed > 0 {
+            let f = &counters.failures;
+            println!(
+                "   (Connect: {}, Receive: {}, Length: {}, Exceptions: {})",
+                f.connect, f.receive, f.length, f.exceptions
+            );
+        }
+        if !counters.errors.is_empty() {
+            let errors: Vec<String> = counters
+                .errors
+                .iter()
+                .map(|(error, count)| format!("{}: {}", error.replace('_', " "), count))
+                .collect();
+            println!("   ({})", errors.join(", "));
+        }
+        println!("Non-2xx responses:\t{}", counters.unsuccessful);
+        if self.config.keep_alive {
+            println!("Keep-Alive requests:\t{}", counters.keep_alive);
+        }
+        if let Some(rate) = &self.rate {
+            println!("Target rate:\t\t{:.2} [#/sec]", rate.target_per_sec);
+            println!("Late requests:\t\t{}", rate.late);
+            println!("Connections opened:\t{}", rate.connections);
+        }
+        self.print_throughput();
+
+        println!();
+        self.print_connection_times();
+        println!();
+        self.print_percentiles();
+        self.print_steps();
+        self.print_status_codes();
+        self.print_assertions();
+    }
+
+    /// Whether all assertions passed, if there are any
+    pub fn passed(&self) -> bool {
+        self.assertions.iter().all(|a| a.passed)
+    }
+
+    fn print_throughput(&self) {
+        let t = &self.throughput;
+        println!("Total transferred:\t{} bytes", t.bytes_received);
+        if self.config.posting {
+            println!("Total body sent:\t{}", t.bytes_sent);
+        }
+        println!("HTML transferred:\t{} bytes", t.body_bytes_received);
+
+        if t.requests_per_sec == 0.0 {
+            return;
+        }
+        println!(
+            "Requests per second:\t{:.2} [#/sec] (mean)",
+            t.requests_per_sec
+        );
+        println!("Time per request:\t{:.3} [ms] (mean)", t.time_per_request);
+        println!(
+            "Time per request:\t{:.3} [ms] (mean, across all concurrent requests)",
+            t.time_per_request_all_concurrent
+        );
+        println!(
+            "Transfer rate:\t\t{:.2} [Kbytes/sec] received",
+            t.kbytes_received_per_sec
+        );
+        if self.config.posting {
+            println!("\t\t\t{:.2} kb/s sent", t.kbytes_sent_per_sec);
+            println!(
+                "\t\t\t{:.2} kb/s total",
+                t.kbytes_sent_per_sec + t.kbytes_received_per_sec
+            );
+        }
+    }
+
+    fn print_connection_times(&self) {
+        if let Some(times) = &self.connection_times {
+            println!("Connection Times (ms)");
+            println!(
+                "{:<12}{:>9}{:>9}{:>8}{:>9}{:>9}",
+                "", "min", "mean", "[+/-sd]", "median", "max"
+            );
+            print_connection_times_row("Connect:", &times.connect);
+            print_connection_times_row("Processing:", &times.processing);
+            print_connection_times_row("Waiting:", &times.waiting);
+            print_connection_times_row("Total:", &times.total);
+        }
+    }
+
+    fn print_percentiles(&self) {
+        if self.percentiles.is_empty() {
+            return;
+        }
+
+        println!("Percentage of the requests served within a certain time (ms)");
+        let corrected = self.percentiles.iter().any(|p| p.corrected.is_some());
+        if corrected {
+            println!("\traw\tcorrected (from when requests should have been sent)");
+        }
+        for p in &self.percentiles {
+            let time = match p.corrected {
+                Some(corrected) => format!("{:.3}\t{:.3}", p.time, corrected),
+                None => format!("{:.3}", p.time),
+            };
+            if p.percentage == 100 {
+                println!("100%\t{} (longest request)", time);
+            } else {
+                println!("{}%\t{}", p.percentage, time);
+            }
+        }
+    }
+
+    fn print_steps(&self) {
+        if self.steps.is_empty() {
+            return;
+        }
+
+        println!();
+        println!("Load steps (ms)");
+        println!(
+            "{:>4}{:>9}{:>9}{:>10}{:>10}{:>9}{:>9}{:>9}{:>8}",
+            "step", "level", "start[s]", "requests", "req/s", "mean", "p50", "p99", "failed"
+        );
+        for s in &self.steps {
+            println!(
+                "{:>4}{:>9.0}{:>9.1}{:>10}{:>10.1}{:>9.3}{:>9.3}{:>9.3}{:>8}",
+                s.step,
+                s.level,
+                s.start_secs,
+                s.requests,
+                s.requests_per_sec,
+                s.mean,
+                s.p50,
+                s.p99,
+                s.failed
+            );
+        }
+    }
+
+    fn print_status_codes(&self) {
+        let counters = &self.counters;
+        if counters.status_codes.is_empty() {
+            return;
+        }
+
+        println!();
+        println!("Status code distribution");
+        for (class, count) in &counters.status_classes {
+            println!("  {}:\t{}", class, count);
+            let codes = counters
+                .status_codes
+                .iter()
+                .filter(|(status, _)| format!("{}xx", *status / 100) == *class);
+            for (status, count) in codes {
+                println!("    {}:\t{}", status, count);
+            }
+        }
+    }
+
+    fn print_assertions(&self) {
+        if self.assertions.is_empty() {
+            return;
+        }
+
+        println!();
+        println!("Assertions");
+        for a in &self.assertions {
+            let outcome = if a.passed { "passed" } else { "FAILED" };
+            println!("  {}\t{}\t(actual {})", outcome, a.assertion, a.actual);
+        }
+    }
+}
+
+fn print_connection_times_row(name: &str, stats: &TimeStats) {
+    println!(
+        "{:<12}{:>9.3}{:>9.3}{:>8.3}{:>9.3}{:>9.3}",
+        name, stats.min, stats.mean, stats.std_dev, stats.median, stats.max
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_write_json() {
+        let report = Report {
+            version: VERSION,
+            config: Config {
+                url: "http://localhost/".into(),
+                concurrency: 2,
+                requests: Some(10),
+                keep_alive: false,
+                posting: false,
+                accept_variable_length: false,
+            },
+            server: Server {
+                software: "nginx".into(),
+                hostname: "localhost".into(),
+                port: 80,
+                tls_protocol: None,
+                document_path: "/".into(),
+                document_length: Some(12),
+            },
+            counters: Counters {
+                sent: 10,
+                complete: 10,
+                successful: 9,
+                unsuccessful: 1,
+                failed: 1,
+                failures: Failures {
+                    connect: 0,
+                    receive: 1,
+                    length: 0,
+                    exceptions: 0,
+                },
+                errors: vec![("connection_reset".to_owned(), 1)]
+                    .into_iter()
+                    .collect(),
+                keep_alive: 0,
+                status_codes: vec![(200, 9), (404, 1)].into_iter().collect(),
+                status_classes: vec![("2xx".to_owned(), 9), ("4xx".to_owned(), 1)]
+                    .into_iter()
+                    .collect(),
+            },
+            throughput: Throughput {
+                time_taken_secs: 0.5,
+                bytes_sent: 500,
+                bytes_received: 2048,
+                body_bytes_received: 120,
+                requests_per_sec: 20.0,
+                time_per_request: 100.0,
+                time_per_request_all_concurrent: 50.0,
+                kbytes_received_per_sec: 4.0,
+                kbytes_sent_per_sec: 0.9765625,
+            },
+            rate: None,
+            connection_times: None,
+            percentiles: vec![Percentile {
+                percentage: 50,
+                time: 1.5,
+                corrected: None,
+            }],
+            steps: vec![],
+            assertions: vec![],
+        };
+
+        let mut out = vec![];
+        report.write_json(&mut out).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+
+        assert_eq!(2, json["version"]);
+        assert_eq!("nginx", json["server"]["software"]);
+        assert_eq!(9, json["counters"]["successful"]);
+        assert_eq!(1, json["counters"]["errors"]["connection_reset"]);
+        assert_eq!(9, json["counters"]["status_codes"]["200"]);
+        assert_eq!(1, json["counters"]["status_classes"]["4xx"]);
+        assert_eq!(20.0, json["throughput"]["requests_per_sec"]);
+        assert!(json["connection_times"].is_null());
+        assert_eq!(1.5, json["percentiles"][0]["time"]);
+        assert!(json["percentiles"][0].get("corrected").is_none());
+    }
+}
@@ -1,51 +1,273 @@
-use std::collections::HashMap;
-use std::time::{Duration, Instant};
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::io::Write;
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+use chrono::{DateTime, Local};
+
+use hdrhistogram::Histogram;
 use mio::Token;
 use url::Url;
 
 use crate::connection::ConnectionState;
 use crate::ctx::Ctx;
+use crate::progress::Snapshot;
+use crate::report::{
+    self, Config, ConnectionTimes, Counters, Failures, Percentile, Rate, Report, Server,
+    Throughput, TimeStats,
+};
+use crate::stats::{self, Latencies, RequestTimes};
 
 pub struct Reporter {
     heartbeatres: Option<usize>,
     done: usize,
     connections: HashMap<Token, ConnectionStats>,
+    latencies: Latencies,
+    requests: Option<Vec<(SystemTime, RequestTimes)>>, // only when collecting all requests
+    clock: (Instant, SystemTime),                      // to convert instants to wall clock time
+    steps: Vec<Step>,                                  // with a load profile
+    significant_digits: u8,
+    window: Option<Window>, // when showing the progress
+    warming_up: bool,       // no snapshots until the benchmark starts
     started: Option<Instant>,
     finished: Option<Instant>,
 }
 
+/// Requests since the last snapshot of the progress
+struct Window {
+    snapshots: Sender<Snapshot>,
+    interval: Duration,
+    started: Instant,
+    requests: usize,
+    failed_before: usize, // failed requests in all previous windows
+    bytes: usize,
+    total: Histogram<u64>,
+}
+
+/// A step of a load profile
+struct Step {
+    step: usize,
+    level: f64,
+    started: Instant,
+    total: Histogram<u64>,
+    failed_before: usize, // failed requests in all previous steps
+}
+
 struct ConnectionStats {
     state: State,
-    times: Vec<Duration>,
-    ctimes: Vec<Duration>, // connection times
+    bytes_sent: usize,
+    bytes_received: usize,
+    body_received: usize,
+    scheduled: Option<Instant>, // when the next request should be sent
 }
 
 #[derive(Debug)]
 enum State {
     Unconnected,
     Connecting(Instant),
-    Connected,
-    Read(Instant),
+    Connected(Option<(Instant, Duration)>), // when connecting started and how long it took
+    Read(Timing),
+}
+
+/// Timing of the request in flight
+#[derive(Debug)]
+struct Timing {
+    started: Instant, // connecting or, for a reused connection, sending
+    connect: Duration,
+    sent: Instant,
+    scheduled: Option<Instant>,
+    first_byte: Option<Instant>,
+}
+
+impl Timing {
+    fn finish(&self) -> RequestTimes {
+        let done = Instant::now();
+        // not how long the connection was idle before sending
+        let total = self.connect + (done - self.sent);
+        RequestTimes {
+            connect: self.connect,
+            wait: self.first_byte.unwrap_or(done) - self.sent,
+            total,
+            started: self.started,
+            // from when it was due, which includes connecting and any delay
+            // in sending, e.g. by a stalled server
+            corrected: self
+                .scheduled
+                .map(|scheduled| done.saturating_duration_since(scheduled).max(total)),
+        }
+    }
 }
 
 impl Reporter {
-    pub fn new(heartbeatres: Option<usize>) -> Self {
+    pub fn new(heartbeatres: Option<usize>, significant_digits: u8) -> Self {
         Reporter {
             heartbeatres,
             done: 0,
             connections: HashMap::new(),
+            latencies: Latencies::new(significant_digits),
+            requests: None,
+            clock: (Instant::now(), SystemTime::now()),
+            steps: vec![],
+            significant_digits,
+            window: None,
+            warming_up: false,
             started: None,
             finished: None,
         }
     }
 
+    /// Keeps the times of every single request, e.g. to export them for gnuplot
+    pub fn collect_requests(&mut self) {
+        self.requests = Some(vec![]);
+    }
+
+    /// From now on, requests belong to this step of the load profile.
+    /// `failed` counts all failed requests so far.
+    pub fn begin_step(&mut self, step: usize, level: f64, failed: usize) {
+        self.steps.push(Step {
+            step,
+            level,
+            started: Instant::now(),
+            total: stats::histogram(self.significant_digits),
+            failed_before: failed,
+        });
+    }
+
+    /// Starts timing the benchmark. Anything recorded before, e.g. during
+    /// the warm-up, is discarded.
     pub fn start(&mut self) {
+        self.done = 0;
+        self.latencies = Latencies::new(self.significant_digits);
+        if let Some(requests) = &mut self.requests {
+            requests.clear();
+        }
+        self.steps.clear();
+        if let Some(window) = &mut self.window {
+            window.started = Instant::now();
+            window.requests = 0;
+            window.failed_before = 0; // failures are counted from zero again
+            window.bytes = 0;
+            window.total = stats::histogram(self.significant_digits);
+        }
+        for stats in self.connections.values_mut() {
+            stats.bytes_sent = 0;
+            stats.bytes_received = 0;
+            stats.body_received = 0;
+            stats.scheduled = None;
+            if let State::Connected(Some(_)) = stats.state {
+                // the next request doesn't include connecting, that was before
+                stats.state = State::Connected(None);
+            }
+        }
+        self.warming_up = false;
         self.started = Some(Instant::now());
+        self.finished = None;
+    }
+
+    /// Until the benchmark starts, requests are only for warming up and
+    /// aren't part of the progress
+    pub fn warm_up(&mut self) {
+        self.warming_up = true;
+    }
+
+    /// Stops timing the benchmark, e.g. when the time is up. Requests still
+    /// in flight are answered after that.
+    pub fn stop(&mut self) {
+        self.finished.get_or_insert_with(Instant::now);
     }
 
     pub fn end(&mut self) {
-        self.finished = Some(Instant::now());
+        self.stop();
+        self.window = None; // no more snapshots
+    }
+
+    /// Sends snapshots of the progress at this interval
+    pub fn show_progress(&mut self, snapshots: Sender<Snapshot>, interval: Duration) {
+        self.window = Some(Window {
+            snapshots,
+            interval,
+            started: Instant::now(),
+            requests: 0,
+            failed_before: 0,
+            bytes: 0,
+            total: stats::histogram(self.significant_digits),
+        });
+    }
+
+    /// When the next snapshot of the progress is due, if it is shown
+    pub fn next_snapshot(&self) -> Option<Instant> {
+        if self.warming_up {
+            return None;
+        }
+        self.window
+            .as_ref()
+            .map(|window| window.started + window.interval)
+    }
+
+    /// Sends a snapshot of the progress and starts a new window.
+    /// `failed` counts all failed requests so far.
+    pub fn snapshot(&mut self, failed: usize, connections: usize) {
+        if self.warming_up {
+            return;
+        }
+        if let Some(window) = &mut self.window {
+            let now = Instant::now();
+            let fresh = stats::histogram(self.significant_digits);
+            let snapshot = Snapshot {
+                end: SystemTime::now(),
+                window: now - window.started,
+                requests: window.requests,
+                failed: failed - window.failed_before,
+                connections,
+                bytes: window.bytes,
+                total: std::mem::replace(&mut window.total, fresh),
+            };
+            // every interval, unless the benchmark was too busy
+            window.started = if now - window.started < 2 * window.interval {
+                window.started + window.interval
+            } else {
+                now
+            };
+            window.requests = 0;
+            window.failed_before = failed;
+            window.bytes = 0;
+            // nobody is interested anymore if that fails
+            let _ = window.snapshots.send(snapshot);
+        }
+    }
+
+    /// Adds the requests and connections of another thread
+    pub fn merge(&mut self, other: Reporter) {
+        self.done += other.done;
+        self.latencies.merge(&other.latencies);
+        if let (Some(requests), Some(other)) = (&mut self.requests, other.requests) {
+            requests.extend(other);
+        }
+
+        // each thread numbers its connections from 0
+        let offset = self.connections.keys().map(|t| t.0 + 1).max().unwrap_or(0);
+        for (token, stats) in other.connections {
+            self.connections.insert(Token(token.0 + offset), stats);
+        }
+
+        for other in other.steps {
+            match self.steps.iter_mut().find(|s| s.step == other.step) {
+                Some(step) => {
+                    step.level += other.level;
+                    step.started = step.started.min(other.started);
+                    step.total
+                        .add(&other.total)
+                        .expect("Histograms must have the same bounds");
+                    step.failed_before += other.failed_before;
+                }
+                None => self.steps.push(other),
+            }
+        }
+        self.steps.sort_by_key(|s| s.step);
+
+        self.started = self.started.min(other.started).or(other.started);
+        self.finished = self.finished.max(other.finished);
     }
 
     pub fn connection_state_changed(&mut self, conn: &Token, new_state: &ConnectionState) {
@@ -54,18 +276,33 @@ impl Reporter {
         use ConnectionState as Conn;
         use State::*;
         match (&stats.state, new_state) {
-            (Connected, Conn::Read) => {
-                stats.state = Read(Instant::now());
+            (Connected(connected), Conn::Read) => {
+                let sent = Instant::now();
+                let (started, connect) = connected.unwrap_or((sent, Duration::from_secs(0)));
+                stats.state = Read(Timing {
+                    started,
+                    connect,
+                    sent,
+                    scheduled: stats.scheduled.take(),
+                    first_byte: None,
+                });
+            }
+            (Read(timing), Conn::Unconnected) => {
+                let times = timing.finish();
+                stats.state = Unconnected;
+                self.record(times);
             }
-            (Read(started), Conn::Unconnected) => {
-                stats.times.push(Instant::now() - *started);
+            (Read(timing), Conn::Connected) => {
+                // keep-alive: response complete, connection stays open
+                let times = timing.finish();
+                stats.state = Connected(None);
+                self.record(times);
+            }
+            (_, Conn::Unconnected) => {
                 stats.state = Unconnected;
-                self.done += 1;
-                self.print_heartbeat();
             }
             (Connecting(started), Conn::Connected) => {
-                stats.ctimes.push(Instant::now() - *started);
-                stats.state = Connected;
+                stats.state = Connected(Some((*started, Instant::now() - *started)));
             }
             (_, Conn::Connecting) => {
                 stats.state = Connecting(Instant::now());
@@ -77,9 +314,60 @@ impl Reporter {
         }
     }
 
+    fn record(&mut self, times: RequestTimes) {
+        self.latencies.record(&times);
+        if let Some(step) = self.steps.last_mut() {
+            step.total.saturating_record(times.total.as_micros() as u64);
+        }
+        if let Some(window) = &mut self.window {
+            window.requests += 1;
+            window
+                .total
+                .saturating_record(times.total.as_micros() as u64);
+        }
+        if let Some(requests) = &mut self.requests {
+            let (instant, wall_clock) = self.clock;
+            requests.push((wall_clock + (times.started - instant), times));
+        }
+        self.done += 1;
+        self.print_heartbeat();
+    }
+
+    pub fn response_started(&mut self, conn: &Token) {
+        if let State::Read(timing) = &mut self.get_or_insert(conn).state {
+            timing.first_byte.get_or_insert_with(Instant::now);
+        }
+    }
+
+    /// The next request on this connection should have been sent at this time
+    pub fn request_scheduled(&mut self, conn: &Token, scheduled: Instant) {
+        self.get_or_insert(conn).scheduled = Some(scheduled);
+    }
+
+    pub fn bytes_sent(&mut self, conn: &Token, nbytes: usize) {
+        self.get_or_insert(conn).bytes_sent += nbytes;
+    }
+
+    pub fn bytes_received(&mut self, conn: &Token, nbytes: usize, body: usize) {
+        let stats = self.get_or_insert(conn);
+        stats.bytes_received += nbytes;
+        stats.body_received += body;
+        if let Some(window) = &mut self.window {
+            window.bytes += nbytes;
+        }
+    }
+
+    pub fn request_failed(&mut self, conn: &Token) {
+        self.get_or_insert(conn).state = State::Unconnected;
+    }
+
+    pub fn request_discarded(&mut self, conn: &Token) {
+        self.get_or_insert(conn).state = State::Connected(None);
+    }
+
     fn print_heartbeat(&self) {
         if let Some(heartbeatres) = self.heartbeatres {
-            if self.done % heartbeatres == 0 {
+            if self.done.is_multiple_of(heartbeatres) {
                 println!("Completed {} requests", self.done);
             }
         }
@@ -91,8 +379,10 @@ impl Reporter {
                 *conn,
                 ConnectionStats {
                     state: State::Unconnected,
-                    times: vec![],
-                    ctimes: vec![],
+                    bytes_sent: 0,
+                    bytes_received: 0,
+                    body_received: 0,
+                    scheduled: None,
                 },
             );
         }
@@ -101,160 +391,470 @@ impl Reporter {
             .expect("Just inserted, must be present!")
     }
 
-    pub fn print(&self, url: &Url, ctx: &Ctx) {
-        println!(
-            "Server Software:\t{}",
-            ctx.server_name.as_ref().unwrap_or(&String::new())
-        );
-        println!("Server Hostname:\t{}", url.host_str().unwrap());
-        println!("Server Port:\t\t{}", url.port_or_known_default().unwrap());
-        println!();
+    pub fn report(&self, url: &Url, ctx: &Ctx) -> Report {
+        let time_spent = self.finished.unwrap() - self.started.unwrap();
+        let secs = time_spent.as_secs_f64();
+
+        let sent: usize = self.connections.values().map(|c| c.bytes_sent).sum();
+        let received: usize = self.connections.values().map(|c| c.bytes_received).sum();
+        let body: usize = self.connections.values().map(|c| c.body_received).sum();
+
+        let done = ctx.total_responses() as f64;
+        let rate = |n: f64| if secs == 0.0 { 0.0 } else { n / secs };
+        let per_request = |n: f64| if done == 0.0 { 0.0 } else { n / done };
+
+        Report {
+            version: report::VERSION,
+            config: Config {
+                url: url.to_string(),
+                concurrency: ctx.concurrency,
+                requests: Some(ctx.max_requests()).filter(|&n| n != usize::MAX),
+                keep_alive: ctx.keep_alive,
+                posting: ctx.posting,
+                accept_variable_length: ctx.accept_variable_length,
+            },
+            server: Server {
+                software: ctx.server_name.clone().unwrap_or_default(),
+                hostname: url.host_str().unwrap().to_owned(),
+                port: url.port_or_known_default().unwrap(),
+                tls_protocol: ctx.tls_protocol.clone(),
+                document_path: url.path().to_owned(),
+                document_length: ctx.doclen,
+            },
+            counters: Counters {
+                sent: ctx.sent_requests,
+                complete: ctx.unsuccessful_responses + ctx.successful_responses,
+                successful: ctx.successful_responses,
+                unsuccessful: ctx.unsuccessful_responses,
+                failed: ctx.failures.total(),
+                failures: Failures {
+                    connect: ctx.failures.connect,
+                    receive: ctx.failures.receive,
+                    length: ctx.failures.length,
+                    exceptions: ctx.failures.exceptions,
+                },
+                errors: ctx
+                    .errors
+                    .iter()
+                    .map(|(error, count)| (error.name().to_owned(), *count))
+                    .collect(),
+                keep_alive: ctx.keep_alive_responses,
+                status_codes: ctx.status_codes.clone(),
+                status_classes: status_classes(&ctx.status_codes),
+            },
+            throughput: Throughput {
+                time_taken_secs: secs,
+                bytes_sent: sent,
+                bytes_received: received,
+                body_bytes_received: body,
+                requests_per_sec: rate(done),
+                time_per_request: per_request(self.mean_concurrency(ctx) * secs * 1000.0),
+                time_per_request_all_concurrent: per_request(secs * 1000.0),
+                kbytes_received_per_sec: rate(received as f64 / 1024.0),
+                kbytes_sent_per_sec: rate(sent as f64 / 1024.0),
+            },
+            rate: ctx.schedule.as_ref().map(|schedule| Rate {
+                target_per_sec: schedule.rate(),
+                late: schedule.late,
+                connections: ctx.connections_opened(),
+            }),
+            connection_times: self.connection_times(),
+            percentiles: self.percentiles(),
+            steps: self.steps(ctx.failures.total()),
+            assertions: vec![], // checked against the report
+        }
+    }
 
-        println!("Document Path:\t\t{}", url.path());
-        if let Some(doclen) = ctx.doclen {
-            println!("Document Length:\t{} bytes", doclen);
+    /// Concurrency over the time taken. With a load profile, the mean of
+    /// its steps weighted by how long each one lasted.
+    fn mean_concurrency(&self, ctx: &Ctx) -> f64 {
+        let finished = match self.finished {
+            Some(finished) if ctx.schedule.is_none() && !self.steps.is_empty() => finished,
+            _ => return ctx.concurrency as f64,
+        };
+        let ends = self
+            .steps
+            .iter()
+            .skip(1)
+            .map(|next| next.started)
+            .chain(Some(finished));
+        let (mut levels, mut secs) = (0.0, 0.0);
+        for (step, end) in self.steps.iter().zip(ends) {
+            let duration = end.saturating_duration_since(step.started).as_secs_f64();
+            levels += step.level * duration;
+            secs += duration;
         }
-        println!();
+        if secs == 0.0 {
+            ctx.concurrency as f64
+        } else {
+            levels / secs
+        }
+    }
 
-        println!("Concurrency Level:\t{}", ctx.concurrency);
-        let time_spent = self.finished.unwrap() - self.started.unwrap();
-        println!(
-            "Time taken for tests:\t{}.{:03} seconds",
-            time_spent.as_secs(),
-            time_spent.as_millis() % 1000
-        );
-        println!(
-            "Complete requests:\t{}",
-            ctx.unsuccessful_responses + ctx.successful_responses
-        );
-        println!("Failed requests:\t{}", ctx.failed_responses);
-        println!("Non-2xx responses:\t{}", ctx.unsuccessful_responses);
+    fn connection_times(&self) -> Option<ConnectionTimes> {
+        if self.latencies.is_empty() {
+            return None;
+        }
 
-        println!();
-        self.print_connection_times();
-        println!();
-        self.print_response_times();
+        let l = &self.latencies;
+        Some(ConnectionTimes {
+            connect: time_stats(&l.connect),
+            processing: time_stats(&l.processing),
+            waiting: time_stats(&l.waiting),
+            total: time_stats(&l.total),
+        })
     }
 
-    fn print_connection_times(&self) {
-        let mut ctimes: Vec<Duration> = self
-            .connections
-            .iter()
-            .flat_map(|(_, c)| c.ctimes.clone())
-            .collect();
-
-        if ctimes.is_empty() {
-            return;
+    fn percentiles(&self) -> Vec<Percentile> {
+        let total = &self.latencies.total;
+        if total.len() <= 1 {
+            return vec![];
         }
 
-        ctimes.sort_unstable();
-
-        println!("Connection Times (ms)");
-        println!("\t\tmin  mean[+/-sd] median   max");
-        println!(
-            "Connect:\t{: >3}{: >5.0}{: >6.1}{: >5}{: >10}",
-            min(&ctimes),
-            mean(&ctimes),
-            std_dev(&ctimes),
-            median(&ctimes),
-            max(&ctimes)
-        );
+        let corrected = &self.latencies.corrected;
+        [50, 66, 75, 80, 90, 95, 98, 99, 100]
+            .iter()
+            .map(|&percentage| Percentile {
+                percentage,
+                time: stats::percentile(total, percentage as f64),
+                corrected: if corrected.is_empty() {
+                    None
+                } else {
+                    Some(stats::percentile(corrected, percentage as f64))
+                },
+            })
+            .collect()
     }
 
-    fn print_response_times(&self) {
-        let mut all_times: Vec<Duration> = self
-            .connections
+    /// Summary of each load step, `failed` counts all failed requests
+    fn steps(&self, failed: usize) -> Vec<report::Step> {
+        let ends = self
+            .steps
             .iter()
-            .flat_map(|(_, c)| c.times.clone())
-            .collect();
-        all_times.sort_unstable();
+            .skip(1)
+            .map(|next| (next.started, next.failed_before))
+            .chain(Some((self.finished.unwrap(), failed)));
 
-        if all_times.len() > 1 {
-            println!("Percentage of the requests served within a certain time (ms)");
+        self.steps
+            .iter()
+            .zip(ends)
+            .map(|(step, (end, failed_after))| {
+                let secs = (end - step.started).as_secs_f64();
+                let requests = step.total.len() as usize;
+                let time = |f: fn(&Histogram<u64>) -> f64| {
+                    if step.total.is_empty() {
+                        0.0
+                    } else {
+                        f(&step.total)
+                    }
+                };
+                report::Step {
+                    step: step.step,
+                    level: step.level,
+                    start_secs: (step.started - self.started.unwrap()).as_secs_f64(),
+                    duration_secs: secs,
+                    requests,
+                    requests_per_sec: if secs == 0.0 {
+                        0.0
+                    } else {
+                        requests as f64 / secs
+                    },
+                    mean: time(stats::mean),
+                    p50: time(stats::median),
+                    p99: time(|h| stats::percentile(h, 99.0)),
+                    failed: failed_after - step.failed_before,
+                }
+            })
+            .collect()
+    }
 
-            for percentage in [50, 66, 75, 80, 90, 95, 98, 99].iter() {
-                let idx = all_times.len() / 100 * percentage;
-                println!("{}%\t{}", percentage, all_times[idx].as_millis());
-            }
-            if let Some(longest) = all_times.last() {
-                println!("100%\t{} (longest request)", longest.as_millis());
-            }
-        }
+    pub fn latencies(&self) -> &Latencies {
+        &self.latencies
     }
-}
 
-fn min(times: &[Duration]) -> u128 {
-    times.first().unwrap().as_millis()
-}
+    pub fn write_hgrm<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        stats::write_hgrm(&self.latencies.total, out)
+    }
 
-fn mean(times: &[Duration]) -> f32 {
-    let sum: Duration = times.iter().sum();
-    sum.as_millis() as f32 / times.len() as f32
+    pub fn write_csv_percentiles<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        stats::write_csv_percentiles(&self.latencies.total, out)
+    }
+
+    /// Writes all collected requests sorted by their total time, like ab
+    pub fn write_gnuplot<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let mut requests: Vec<&(SystemTime, RequestTimes)> =
+            self.requests.iter().flatten().collect();
+        requests.sort_by_key(|(_, times)| times.total);
+        write_gnuplot(&requests, out)
+    }
 }
 
-fn std_dev(times: &[Duration]) -> f32 {
-    let mean = mean(times);
-    let variance = times
-        .iter()
-        .map(|t| (t.as_millis() as f32 - mean).powf(2.0))
-        .sum::<f32>()
-        / (times.len() - 1) as f32;
-    variance.sqrt()
+/// Counts the responses per class, e.g. 2xx
+fn status_classes(status_codes: &BTreeMap<u16, usize>) -> BTreeMap<String, usize> {
+    let mut classes = BTreeMap::new();
+    for (status, count) in status_codes {
+        *classes.entry(format!("{}xx", status / 100)).or_insert(0) += count;
+    }
+    classes
 }
 
-fn median(times: &[Duration]) -> u128 {
-    times[times.len() / 2].as_millis()
+fn time_stats(hist: &Histogram<u64>) -> TimeStats {
+    TimeStats {
+        min: stats::min(hist),
+        mean: stats::mean(hist),
+        std_dev: stats::std_dev(hist),
+        median: stats::median(hist),
+        max: stats::max(hist),
+    }
 }
 
-fn max(times: &[Duration]) -> u128 {
-    times.last().unwrap().as_millis()
+fn write_gnuplot<W: Write>(
+    requests: &[&(SystemTime, RequestTimes)],
+    out: &mut W,
+) -> io::Result<()> {
+    writeln!(out, "starttime\tseconds\tctime\tdtime\tttime\twait")?;
+    for (started, times) in requests {
+        writeln!(
+            out,
+            "{}\t{}\t{}\t{}\t{}\t{}",
+            DateTime::<Local>::from(*started).format("%a %b %d %H:%M:%S %Y"),
+            started
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            times.connect.as_millis(),
+            (times.total - times.connect).as_millis(),
+            times.total.as_millis(),
+            times.wait.as_millis()
+        )?;
+    }
+    Ok(())
 }
 
 #[cfg(test)]
-#[allow(clippy::float_cmp)]
 mod tests {
+    use std::sync::mpsc;
+    use std::thread;
+
+    use crate::ctx::Error;
+    use crate::schedule::Schedule;
+
     use super::*;
 
     #[test]
-    fn test_min() {
-        assert_eq!(1, min(&[d(1), d(2), d(3)]));
-        assert_eq!(2, min(&[d(2), d(3)]));
+    fn test_corrected_time() {
+        let scheduled = Instant::now() - Duration::from_millis(20);
+        let sent = scheduled + Duration::from_millis(5);
+        let timing = Timing {
+            started: sent,
+            connect: Duration::from_secs(0),
+            sent,
+            scheduled: Some(scheduled),
+            first_byte: None,
+        };
+        let times = timing.finish();
+        assert_eq!(
+            Some(times.total + Duration::from_millis(5)),
+            times.corrected
+        );
+
+        // connected for this request after it was due, then sent
+        let started = scheduled + Duration::from_millis(1);
+        let timing = Timing {
+            started,
+            connect: Duration::from_millis(4),
+            sent: started + Duration::from_millis(4),
+            scheduled: Some(scheduled),
+            first_byte: None,
+        };
+        let times = timing.finish();
+        assert_eq!(
+            Some(times.total + Duration::from_millis(1)),
+            times.corrected
+        );
+    }
+
+    #[test]
+    fn test_total_time_of_idle_connection() {
+        // connected, then idle until the request was due
+        let started = Instant::now() - Duration::from_millis(100);
+        let timing = Timing {
+            started,
+            connect: Duration::from_millis(5),
+            sent: started + Duration::from_millis(90),
+            scheduled: None,
+            first_byte: None,
+        };
+        let times = timing.finish();
+        assert!(times.total >= Duration::from_millis(15));
+        assert!(times.total < Duration::from_millis(50), "{:?}", times.total);
+    }
+
+    #[test]
+    fn test_snapshot_after_warmup() {
+        let (snapshots, received) = mpsc::channel();
+        let mut reporter = Reporter::new(None, 3);
+        reporter.show_progress(snapshots, Duration::from_secs(1));
+        reporter.start();
+        reporter.warm_up();
+
+        // the warm-up, without a snapshot even after three failed requests
+        let conn = Token(0);
+        let request = |reporter: &mut Reporter| {
+            reporter.connection_state_changed(&conn, &ConnectionState::Connecting);
+            reporter.connection_state_changed(&conn, &ConnectionState::Connected);
+            reporter.connection_state_changed(&conn, &ConnectionState::Read);
+            reporter.bytes_received(&conn, 100, 12);
+            reporter.connection_state_changed(&conn, &ConnectionState::Unconnected);
+        };
+        request(&mut reporter);
+        assert_eq!(None, reporter.next_snapshot());
+        reporter.snapshot(3, 1);
+        request(&mut reporter);
+
+        reporter.start();
+        reporter.snapshot(1, 1);
+
+        let first = received.try_recv().unwrap();
+        assert_eq!((0, 1, 0), (first.requests, first.failed, first.bytes));
+        assert!(first.total.is_empty());
+        assert!(received.try_recv().is_err());
     }
 
     #[test]
-    fn test_mean() {
-        assert_eq!(2.0, mean(&[d(1), d(2), d(3)]));
-        assert_eq!(2.5, mean(&[d(2), d(3)]));
-        assert_eq!(5.0, mean(&[d(4), d(5), d(6)]));
-        assert_eq!(6.0, mean(&[d(1), d(1), d(1), d(3), d(24)]));
-        assert_eq!(5.5, mean(&[d(5), d(6)]));
+    fn test_stop() {
+        let mut reporter = Reporter::new(None, 3);
+        reporter.start();
+        reporter.stop();
+        let stopped = reporter.finished;
+        thread::sleep(Duration::from_millis(10));
+        reporter.end();
+        assert_eq!(stopped, reporter.finished);
+
+        reporter.start();
+        assert_eq!(None, reporter.finished);
     }
 
     #[test]
-    fn test_std_dev() {
-        assert_eq!(1.9148543, std_dev(&[d(3), d(5), d(7), d(7)]));
+    fn test_merge() {
+        let request = |reporter: &mut Reporter, conn: Token, nbytes: usize| {
+            reporter.connection_state_changed(&conn, &ConnectionState::Connecting);
+            reporter.connection_state_changed(&conn, &ConnectionState::Connected);
+            reporter.connection_state_changed(&conn, &ConnectionState::Read);
+            reporter.bytes_received(&conn, nbytes, 0);
+            reporter.connection_state_changed(&conn, &ConnectionState::Unconnected);
+        };
+
+        let mut reporter = Reporter::new(None, 3);
+        reporter.start();
+        reporter.begin_step(0, 2.0, 0);
+        request(&mut reporter, Token(0), 100);
+        request(&mut reporter, Token(1), 100);
+
+        let mut other = Reporter::new(None, 3);
+        other.start();
+        other.begin_step(0, 1.0, 0);
+        request(&mut other, Token(0), 50);
+        thread::sleep(Duration::from_millis(10));
+        other.begin_step(1, 2.0, 1);
+        request(&mut other, Token(0), 50);
+
+        reporter.end();
+        thread::sleep(Duration::from_millis(10));
+        other.end();
+        let started = reporter.started;
+        let finished = other.finished;
+
+        let mut ctx = Ctx::new(vec![], 4, 2).unwrap();
+        ctx.schedule = Some(Schedule::new(10.0));
+        let mut other_ctx = Ctx::new(vec![], 4, 1).unwrap();
+        other_ctx.schedule = Some(Schedule::new(5.0));
+        other_ctx.error(Error::ReadTimeout);
+        ctx.merge(other_ctx);
+
+        reporter.merge(other);
+        assert_eq!(started, reporter.started);
+        assert_eq!(finished, reporter.finished);
+        let mut tokens: Vec<usize> = reporter.connections.keys().map(|t| t.0).collect();
+        tokens.sort_unstable();
+        assert_eq!(vec![0, 1, 2], tokens);
+
+        let url = Url::parse("http://localhost").unwrap();
+        let report = reporter.report(&url, &ctx);
+        assert_eq!(300, report.throughput.bytes_received);
+        assert!(report.throughput.time_taken_secs >= 0.01);
+        assert_eq!(Some(15.0), report.rate.map(|rate| rate.target_per_sec));
         assert_eq!(
-            2.13809,
-            std_dev(&[d(2), d(4), d(4), d(4), d(5), d(5), d(7), d(9)])
+            vec![(0, 3.0, 3, 1), (1, 2.0, 1, 0)],
+            report
+                .steps
+                .iter()
+                .map(|step| (step.step, step.level, step.requests, step.failed))
+                .collect::<Vec<_>>()
         );
     }
 
     #[test]
-    fn test_median() {
-        assert_eq!(1, median(&[d(1)]));
-        assert_eq!(2, median(&[d(1), d(2)]));
-        assert_eq!(2, median(&[d(1), d(2), d(3)]));
-        assert_eq!(5, median(&[d(4), d(5), d(6)]));
-        assert_eq!(1, median(&[d(1), d(1), d(1), d(3), d(24)]));
+    fn test_time_per_request_with_profile() {
+        let mut ctx = Ctx::new(vec![], 4, 3).unwrap();
+        (0..4).for_each(|_| ctx.response(200));
+
+        // one connection for a second, then three for another second
+        let mut reporter = Reporter::new(None, 3);
+        reporter.start();
+        reporter.begin_step(0, 1.0, 0);
+        reporter.begin_step(1, 3.0, 0);
+        let started = Instant::now();
+        reporter.started = Some(started);
+        reporter.steps[0].started = started;
+        reporter.steps[1].started = started + Duration::from_secs(1);
+        reporter.finished = Some(started + Duration::from_secs(2));
+
+        let url = Url::parse("http://localhost").unwrap();
+        let throughput = reporter.report(&url, &ctx).throughput;
+        assert_eq!(2.0 * 2.0 * 1000.0 / 4.0, throughput.time_per_request);
+        assert_eq!(
+            2.0 * 1000.0 / 4.0,
+            throughput.time_per_request_all_concurrent
+        );
     }
 
     #[test]
-    fn test_max() {
-        assert_eq!(3, max(&[d(1), d(2), d(3)]));
-        assert_eq!(3, max(&[d(2), d(3)]));
+    fn test_status_classes() {
+        let codes = vec![(200, 5), (204, 1), (404, 2), (500, 1)]
+            .into_iter()
+            .collect();
+        let classes = status_classes(&codes);
+        assert_eq!(
+            vec![("2xx", 6), ("4xx", 2), ("5xx", 1)],
+            classes
+                .iter()
+                .map(|(class, count)| (class.as_str(), *count))
+                .collect::<Vec<_>>()
+        );
     }
 
-    fn d(ms: u64) -> Duration {
-        Duration::from_millis(ms)
+    #[test]
+    fn test_write_gnuplot() {
+        let started = UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        let times = RequestTimes {
+            connect: Duration::from_micros(1500),
+            wait: Duration::from_micros(7900),
+            total: Duration::from_micros(12100),
+            started: Instant::now(),
+            corrected: None,
+        };
+        let mut out = vec![];
+        write_gnuplot(&[&(started, times)], &mut out).unwrap();
+        let gnuplot = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = gnuplot.lines().collect();
+
+        assert_eq!(2, lines.len());
+        assert_eq!("starttime\tseconds\tctime\tdtime\tttime\twait", lines[0]);
+        let fields: Vec<&str> = lines[1].split('\t').collect();
+        assert_eq!(vec!["1000000000", "1", "10", "12", "7"], fields[1..]);
+        // ctime format, e.g. Sun Sep 09 01:46:40 2001, depending on the timezone
+        assert_eq!(5, fields[0].split(' ').count());
+        assert!(fields[0].ends_with(" 2001"));
     }
 }
@@ -0,0 +1,153 @@
+use std::time::{Duration, Instant};
+
+/// Smallest delay after which a request counts as late, below that it's
+/// just the resolution of polling
+const MIN_LATENESS: Duration = Duration::from_millis(1);
+
+/// Sends requests at a fixed rate, no matter how fast the server responds.
+/// The first request is due when the benchmark starts.
+pub struct Schedule {
+    rate: f64, // per second
+    interval: Duration,
+    start: Option<Instant>, // of the schedule or the last change of the rate
+    base: f64,              // requests due before `start`
+    scheduled: usize,       // requests sent so far
+    pub late: usize,        // requests sent after the next one was already due
+}
+
+impl Schedule {
+    pub fn new(rate: f64) -> Self {
+        Schedule {
+            rate,
+            interval: Duration::from_secs_f64(1.0 / rate),
+            start: None,
+            base: 0.0,
+            scheduled: 0,
+            late: 0,
+        }
+    }
+
+    /// Starts the schedule over, forgetting about the requests sent so far
+    pub fn start(&mut self, now: Instant) {
+        self.start = Some(now);
+        self.base = 0.0;
+        self.scheduled = 0;
+        self.late = 0;
+    }
+
+    pub fn rate(&self) -> f64 {
+        self.rate
+    }
+
+    /// Changes the rate from now on
+    pub fn set_rate(&mut self, rate: f64, now: Instant) {
+        if rate != self.rate {
+            let start = self.start.expect("Schedule not started");
+            self.base += now.saturating_duration_since(start).as_secs_f64() * self.rate;
+            self.start = Some(now);
+            self.rate = rate;
+            self.interval = Duration::from_secs_f64(1.0 / rate);
+        }
+    }
+
+    /// When the next request is due
+    pub fn next(&self) -> Instant {
+        self.time_of(self.scheduled)
+    }
+
+    /// How many requests are due and have not been sent yet
+    pub fn due(&self, now: Instant) -> usize {
+        let start = self.start.expect("Schedule not started");
+        let elapsed = now.saturating_duration_since(start).as_secs_f64();
+        let until_now = (self.base + elapsed * self.rate) as usize + 1;
+        until_now.saturating_sub(self.scheduled)
+    }
+
+    /// Takes the next request off the schedule. Returns when it was due.
+    pub fn sent(&mut self, now: Instant) -> Instant {
+        let due = self.next();
+        if now.saturating_duration_since(due) > self.interval.max(MIN_LATENESS) {
+            self.late += 1;
+        }
+        self.scheduled += 1;
+        due
+    }
+
+    /// Adds the rate and late requests of another thread's schedule
+    pub fn merge(&mut self, other: &Schedule) {
+        self.rate += other.rate;
+        self.interval = Duration::from_secs_f64(1.0 / self.rate);
+        self.late += other.late;
+    }
+
+    /// Puts the last request back on the schedule, it was never answered
+    /// and will be sent again
+    pub fn discarded(&mut self) {
+        self.scheduled -= 1;
+    }
+
+    fn time_of(&self, request: usize) -> Instant {
+        let start = self.start.expect("Schedule not started");
+        let offset = (request as f64 - self.base) / self.rate;
+        if offset >= 0.0 {
+            start + Duration::from_secs_f64(offset)
+        } else {
+            // was due before the last change of the rate
+            start
+                .checked_sub(Duration::from_secs_f64(-offset))
+                .unwrap_or(start)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn test_due() {
+        let start = Instant::now();
+        let mut schedule = Schedule::new(100.0);
+        schedule.start(start);
+
+        assert_eq!(1, schedule.due(start));
+        assert_eq!(1, schedule.due(start + ms(9)));
+        assert_eq!(2, schedule.due(start + ms(11)));
+
+        assert_eq!(start, schedule.sent(start));
+        assert_eq!(0, schedule.due(start + ms(9)));
+        assert_eq!(5, schedule.due(start + ms(55)));
+    }
+
+    #[test]
+    fn test_set_rate() {
+        let start = Instant::now();
+        let mut schedule = Schedule::new(100.0);
+        schedule.start(start);
+        schedule.sent(start);
+        schedule.sent(start + ms(10));
+
+        // half an interval has passed at 15ms, so the next one is due
+        // after another half interval at the new rate, then every 100ms
+        schedule.set_rate(10.0, start + ms(15));
+        assert_eq!(0, schedule.due(start + ms(60)));
+        assert_eq!(1, schedule.due(start + ms(70)));
+        assert_eq!(2, schedule.due(start + ms(170)));
+    }
+
+    #[test]
+    fn test_late() {
+        let start = Instant::now();
+        let mut schedule = Schedule::new(100.0);
+        schedule.start(start);
+
+        schedule.sent(start + ms(10));
+        assert_eq!(0, schedule.late);
+        schedule.sent(start + ms(21));
+        assert_eq!(1, schedule.late);
+    }
+}
@@ -0,0 +1,379 @@
+use std::io;
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use hdrhistogram::Histogram;
+
+/// Longest time that can be recorded, anything above is recorded as this
+const MAX_TIME: Duration = Duration::from_secs(60 * 60);
+
+pub struct RequestTimes {
+    pub connect: Duration,
+    pub wait: Duration, // from sending the request until the first byte of the response
+    pub total: Duration,
+    pub started: Instant, // connecting or, for a reused connection, sending
+    pub corrected: Option<Duration>, // from when the request should have been sent
+}
+
+/// Distributions of request times in fixed memory. All values are recorded
+/// in microseconds.
+pub struct Latencies {
+    pub connect: Histogram<u64>,
+    pub processing: Histogram<u64>,
+    pub waiting: Histogram<u64>,
+    pub total: Histogram<u64>,
+    pub corrected: Histogram<u64>, // total, corrected for coordinated omission
+}
+
+/// Histogram for times in microseconds
+pub fn histogram(significant_digits: u8) -> Histogram<u64> {
+    Histogram::new_with_bounds(1, MAX_TIME.as_micros() as u64, significant_digits)
+        .expect("Invalid number of significant digits")
+}
+
+impl Latencies {
+    pub fn new(significant_digits: u8) -> Self {
+        Latencies {
+            connect: histogram(significant_digits),
+            processing: histogram(significant_digits),
+            waiting: histogram(significant_digits),
+            total: histogram(significant_digits),
+            corrected: histogram(significant_digits),
+        }
+    }
+
+    pub fn record(&mut self, times: &RequestTimes) {
+        let micros = |d: Duration| d.as_micros() as u64;
+        self.connect.saturating_record(micros(times.connect));
+        self.processing
+            .saturating_record(micros(times.total - times.connect));
+        self.waiting.saturating_record(micros(times.wait));
+        self.total.saturating_record(micros(times.total));
+        if let Some(corrected) = times.corrected {
+            self.corrected.saturating_record(micros(corrected));
+        }
+    }
+
+    pub fn merge(&mut self, other: &Latencies) {
+        let merge = |into: &mut Histogram<u64>, from: &Histogram<u64>| {
+            into.add(from)
+                .expect("Histograms must have the same bounds")
+        };
+        merge(&mut self.connect, &other.connect);
+        merge(&mut self.processing, &other.processing);
+        merge(&mut self.waiting, &other.waiting);
+        merge(&mut self.total, &other.total);
+        merge(&mut self.corrected, &other.corrected);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.total.is_empty()
+    }
+}
+
+// All statistics are in milliseconds and expect a non-empty histogram
+
+fn millis(micros: u64) -> f64 {
+    micros as f64 / 1000.0
+}
+
+pub fn min(hist: &Histogram<u64>) -> f64 {
+    millis(hist.min())
+}
+
+pub fn mean(hist: &Histogram<u64>) -> f64 {
+    hist.mean() / 1000.0
+}
+
+/// Sample standard deviation, like ab
+pub fn std_dev(hist: &Histogram<u64>) -> f64 {
+    if hist.len() < 2 {
+        return 0.0;
+    }
+    let mean = hist.mean();
+    let squares: f64 = hist
+        .iter_recorded()
+        .map(|v| {
+            let deviation = hist.median_equivalent(v.value_iterated_to()) as f64 - mean;
+            deviation * deviation * v.count_at_value() as f64
+        })
+        .sum();
+    (squares / (hist.len() - 1) as f64).sqrt() / 1000.0
+}
+
+/// Linearly interpolates between the two closest ranks
+pub fn percentile(hist: &Histogram<u64>, percentage: f64) -> f64 {
+    let rank = percentage / 100.0 * (hist.len() - 1) as f64;
+    let lower = value_at_rank(hist, rank.floor() as u64) as f64;
+    let upper = value_at_rank(hist, rank.ceil() as u64) as f64;
+    let weight = rank - rank.floor();
+    (lower * (1.0 - weight) + upper * weight) / 1000.0
+}
+
+pub fn median(hist: &Histogram<u64>) -> f64 {
+    percentile(hist, 50.0)
+}
+
+pub fn max(hist: &Histogram<u64>) -> f64 {
+    millis(hist.max())
+}
+
+/// Value of the n-th smallest sample, starting at zero
+fn value_at_rank(hist: &Histogram<u64>, rank: u64) -> u64 {
+    let mut seen = 0;
+    for v in hist.iter_recorded() {
+        seen += v.count_at_value();
+        if seen > rank {
+            return v.value_iterated_to();
+        }
+    }
+    hist.max()
+}
+
+/// Writes the percentile distribution in the .hgrm format of HdrHistogram,
+/// which can be plotted with its standard tools. Values are in milliseconds.
+pub fn write_hgrm<W: Write>(hist: &Histogram<u64>, out: &mut W) -> io::Result<()> {
+    writeln!(
+        out,
+        "{:>12} {:>14} {:>10} {:>14}\n",
+        "Value", "Percentile", "TotalCount", "1/(1-Percentile)"
+    )?;
+
+    let mut total_count = 0;
+    for v in hist.iter_quantiles(5) {
+        total_count += v.count_since_last_iteration();
+        let quantile = v.quantile_iterated_to();
+        if quantile < 1.0 {
+            writeln!(
+                out,
+                "{:>12.3} {:>2.12} {:>10} {:>14.2}",
+                millis(v.value_iterated_to()),
+                quantile,
+                total_count,
+                1.0 / (1.0 - quantile)
+            )?;
+        } else {
+            writeln!(
+                out,
+                "{:>12.3} {:>2.12} {:>10}",
+                millis(v.value_iterated_to()),
+                quantile,
+                total_count
+            )?;
+        }
+    }
+
+    let (mean, std_dev) = if hist.is_empty() {
+        (0.0, 0.0)
+    } else {
+        (hist.mean() / 1000.0, hist.stdev() / 1000.0)
+    };
+    writeln!(
+        out,
+        "#[Mean    = {:>12.3}, StdDeviation   = {:>12.3}]",
+        mean, std_dev
+    )?;
+    writeln!(
+        out,
+        "#[Max     = {:>12.3}, Total count    = {:>12}]",
+        millis(hist.max()),
+        hist.len()
+    )?;
+    writeln!(
+        out,
+        "#[Buckets = {:>12}, SubBuckets     = {:>12}]",
+        hist.buckets(),
+        // all buckets but the first only use the upper half of the sub buckets
+        2 * hist.distinct_values() / (hist.buckets() as usize + 1)
+    )
+}
+
+/// Writes the percentiles from 0 to 100 as CSV, like ab
+pub fn write_csv_percentiles<W: Write>(hist: &Histogram<u64>, out: &mut W) -> io::Result<()> {
+    writeln!(out, "Percentage served,Time in ms")?;
+    if hist.is_empty() {
+        return Ok(());
+    }
+    for percentage in 0..=100 {
+        writeln!(
+            out,
+            "{},{:.3}",
+            percentage,
+            percentile(hist, percentage as f64)
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+#[allow(clippy::float_cmp)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_min() {
+        assert_eq!(1.0, min(&h(&[d(1), d(2), d(3)])));
+        assert_eq!(2.0, min(&h(&[d(2), d(3)])));
+        assert_eq!(0.25, min(&h(&[us(250), us(300)])));
+        assert_eq!(0.0, min(&h(&[us(0), us(300)])));
+    }
+
+    #[test]
+    fn test_mean() {
+        assert_eq!(2.0, mean(&h(&[d(1), d(2), d(3)])));
+        assert_eq!(2.5, mean(&h(&[d(2), d(3)])));
+        assert_eq!(5.0, mean(&h(&[d(4), d(5), d(6)])));
+        assert_eq!(6.0, mean(&h(&[d(1), d(1), d(1), d(3), d(24)])));
+        assert_eq!(5.5, mean(&h(&[d(5), d(6)])));
+        assert_eq!(0.275, mean(&h(&[us(250), us(300)])));
+    }
+
+    #[test]
+    fn test_std_dev() {
+        assert_eq!(0.0, std_dev(&h(&[d(3)])));
+        assert_close(1.9148542155126762, std_dev(&h(&[d(3), d(5), d(7), d(7)])));
+        assert_close(
+            2.138089935299395,
+            std_dev(&h(&[d(2), d(4), d(4), d(4), d(5), d(5), d(7), d(9)])),
+        );
+    }
+
+    #[test]
+    fn test_median() {
+        assert_eq!(1.0, median(&h(&[d(1)])));
+        assert_eq!(1.5, median(&h(&[d(1), d(2)])));
+        assert_eq!(2.0, median(&h(&[d(1), d(2), d(3)])));
+        assert_eq!(5.0, median(&h(&[d(4), d(5), d(6)])));
+        assert_eq!(1.0, median(&h(&[d(1), d(1), d(1), d(3), d(24)])));
+        assert_close(0.15, median(&h(&[us(100), us(200)])));
+    }
+
+    #[test]
+    fn test_percentile() {
+        let uniform: Vec<Duration> = (1..=100).map(d).collect();
+        assert_close(1.0, percentile(&h(&uniform), 0.0));
+        assert_close(50.5, percentile(&h(&uniform), 50.0));
+        assert_close(90.1, percentile(&h(&uniform), 90.0));
+        assert_close(100.0, percentile(&h(&uniform), 100.0));
+
+        let few = h(&[d(10), d(20), d(30), d(40)]);
+        assert_close(10.0, percentile(&few, 0.0));
+        assert_close(25.0, percentile(&few, 50.0));
+        assert_close(37.0, percentile(&few, 90.0));
+        assert_close(40.0, percentile(&few, 100.0));
+
+        let single = h(&[us(1500)]);
+        assert_close(1.5, percentile(&single, 50.0));
+        assert_close(1.5, percentile(&single, 99.0));
+
+        let sub_millis: Vec<Duration> = (1..=1000).map(us).collect();
+        assert_close(0.5005, percentile(&h(&sub_millis), 50.0));
+        assert_close(0.99001, percentile(&h(&sub_millis), 99.0));
+    }
+
+    #[test]
+    fn test_percentile_within_precision() {
+        // with three significant digits, large values are only approximate
+        let mut hist = Histogram::new_with_bounds(1, MAX_TIME.as_micros() as u64, 3).unwrap();
+        for ms in 1..=10_000 {
+            hist.record(ms * 1000).unwrap();
+        }
+        let p99 = percentile(&hist, 99.0);
+        assert!((p99 - 9900.01).abs() / 9900.01 < 0.001, "p99 was {}", p99);
+    }
+
+    #[test]
+    fn test_max() {
+        assert_eq!(3.0, max(&h(&[d(1), d(2), d(3)])));
+        assert_eq!(3.0, max(&h(&[d(2), d(3)])));
+        assert_eq!(0.3, max(&h(&[us(250), us(300)])));
+    }
+
+    #[test]
+    fn test_merge() {
+        let times = |total| RequestTimes {
+            connect: us(10),
+            wait: us(20),
+            total,
+            started: Instant::now(),
+            corrected: Some(total + us(50)),
+        };
+        let mut a = Latencies::new(3);
+        a.record(&times(us(100)));
+        let mut b = Latencies::new(3);
+        b.record(&times(us(300)));
+        a.merge(&b);
+
+        assert_eq!(2, a.total.len());
+        assert_eq!(0.2, mean(&a.total));
+        assert_eq!(0.19, mean(&a.processing));
+        assert_eq!(0.01, max(&a.connect));
+        assert_eq!(0.25, mean(&a.corrected));
+    }
+
+    #[test]
+    fn test_write_hgrm() {
+        let mut out = vec![];
+        write_hgrm(&h(&[d(1), d(2), d(3), d(4)]), &mut out).unwrap();
+        let hgrm = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = hgrm.lines().collect();
+
+        assert_eq!(
+            "       Value     Percentile TotalCount 1/(1-Percentile)",
+            lines[0]
+        );
+        assert_eq!("", lines[1]);
+        assert_eq!(
+            "       1.000 0.000000000000          1           1.00",
+            lines[2]
+        );
+        assert_eq!(
+            "       4.000 1.000000000000          4",
+            lines[lines.len() - 4]
+        );
+        assert!(lines[lines.len() - 3].starts_with("#[Mean    =        2.500,"));
+        assert!(lines[lines.len() - 2].ends_with("Total count    =            4]"));
+        assert!(lines[lines.len() - 1].ends_with("SubBuckets     =       262144]"));
+    }
+
+    #[test]
+    fn test_write_csv_percentiles() {
+        let mut out = vec![];
+        write_csv_percentiles(&h(&[us(1000), us(2000), us(3000)]), &mut out).unwrap();
+        let csv = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+
+        assert_eq!(102, lines.len());
+        assert_eq!("Percentage served,Time in ms", lines[0]);
+        assert_eq!("0,1.000", lines[1]);
+        assert_eq!("25,1.500", lines[26]);
+        assert_eq!("50,2.000", lines[51]);
+        assert_eq!("100,3.000", lines[101]);
+    }
+
+    /// Histogram that is exact for all values in the tests
+    fn h(times: &[Duration]) -> Histogram<u64> {
+        let mut hist = Histogram::new_with_bounds(1, 1_000_000, 5).unwrap();
+        for time in times {
+            hist.record(time.as_micros() as u64).unwrap();
+        }
+        hist
+    }
+
+    fn assert_close(expected: f64, actual: f64) {
+        assert!(
+            (expected - actual).abs() < 1e-9,
+            "expected {}, got {}",
+            expected,
+            actual
+        );
+    }
+
+    fn d(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    fn us(us: u64) -> Duration {
+        Duration::from_micros(us)
+    }
+}
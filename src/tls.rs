@@ -0,0 +1,183 @@
+use std::io;
+use std::io::{ErrorKind, Read, Write};
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use mio::event::Source;
+use mio::net::TcpStream;
+use mio::{Interest, Registry, Token};
+use rustls::{
+    Certificate, ClientConfig, ClientSession, RootCertStore, ServerCertVerified,
+    ServerCertVerifier, Session, TLSError,
+};
+use webpki::DNSNameRef;
+
+use super::connection::Stream;
+
+/// A non-blocking TLS connection on top of a mio tcp stream. Handshake and
+/// encryption happen as a side effect of reading and writing.
+pub struct TlsStream {
+    sock: TcpStream,
+    session: ClientSession,
+    eof: bool,
+}
+
+impl TlsStream {
+    pub fn connect(
+        addr: SocketAddr,
+        config: &Arc<ClientConfig>,
+        name: DNSNameRef,
+    ) -> io::Result<TlsStream> {
+        Ok(TlsStream {
+            sock: TcpStream::connect(addr)?,
+            session: ClientSession::new(config, name),
+            eof: false,
+        })
+    }
+
+    fn write_tls(&mut self) -> io::Result<()> {
+        while self.session.wants_write() {
+            match self.session.write_tls(&mut self.sock) {
+                Ok(_) => {}
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Creates a factory for tls streams to the given host. The certificate
+/// of the server is verified against the webpki roots unless `insecure`.
+pub fn connector(
+    host: &str,
+    insecure: bool,
+) -> io::Result<impl Fn(SocketAddr) -> io::Result<TlsStream> + Clone> {
+    let mut config = ClientConfig::new();
+    config
+        .root_store
+        .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+
+    let name = match DNSNameRef::try_from_ascii_str(host) {
+        Ok(name) => name.to_owned(),
+        Err(_) if insecure => {
+            // IP addresses cannot be sent as SNI, the name is never verified
+            config.enable_sni = false;
+            DNSNameRef::try_from_ascii_str("localhost")
+                .expect("valid dns name")
+                .to_owned()
+        }
+        Err(_) => {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Cannot verify certificate of {}, use --insecure", host),
+            ))
+        }
+    };
+
+    if insecure {
+        config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(NoCertificateVerification));
+    }
+
+    let config = Arc::new(config);
+    Ok(move |addr| TlsStream::connect(addr, &config, name.as_ref()))
+}
+
+struct NoCertificateVerification;
+
+impl ServerCertVerifier for NoCertificateVerification {
+    fn verify_server_cert(
+        &self,
+        _roots: &RootCertStore,
+        _presented_certs: &[Certificate],
+        _dns_name: DNSNameRef,
+        _ocsp_response: &[u8],
+    ) -> Result<ServerCertVerified, TLSError> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.session.read(buf) {
+                Ok(0) if self.eof => return Ok(0),
+                Ok(0) => {}
+                Ok(n) => return Ok(n),
+                // server sent close_notify
+                Err(e) if e.kind() == ErrorKind::ConnectionAborted => return Ok(0),
+                Err(e) => return Err(e),
+            }
+
+            match self.session.read_tls(&mut self.sock)? {
+                0 => self.eof = true,
+                _ => {
+                    self.session
+                        .process_new_packets()
+                        .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+                    // the handshake may need to respond
+                    self.write_tls()?;
+                }
+            }
+        }
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.session.write(buf)?;
+        self.write_tls()?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.session.flush()?;
+        self.write_tls()
+    }
+}
+
+impl Source for TlsStream {
+    fn register(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        self.sock.register(registry, token, interests)
+    }
+
+    fn reregister(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        self.sock.reregister(registry, token, interests)
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        self.sock.deregister(registry)
+    }
+}
+
+impl Stream for TlsStream {
+    fn take_error(&self) -> io::Result<Option<io::Error>> {
+        self.sock.take_error()
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.sock.peer_addr()
+    }
+
+    fn tls_protocol(&self) -> Option<String> {
+        let version = self.session.get_protocol_version()?;
+        let suite = self.session.get_negotiated_ciphersuite()?;
+        Some(format!(
+            "{},{:?}",
+            format!("{:?}", version).replace('_', "."),
+            suite.suite
+        ))
+    }
+}
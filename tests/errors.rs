@@ -0,0 +1,126 @@
+extern crate rab;
+extern crate serial_test;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::rc::Rc;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use mio::net::TcpStream;
+use serial_test::serial;
+use url::Url;
+
+use rab::benchmarking::benchmark;
+use rab::connection::Connection;
+use rab::ctx::{Ctx, Error};
+use rab::http::{Method, Request};
+use rab::reporting::Reporter;
+
+#[test]
+#[serial]
+fn should_count_refused_connections() {
+    // nothing listens on this port
+    let ctx = bench("127.0.0.1:3002".parse().unwrap(), 3);
+
+    assert_eq!(Some(&3), ctx.errors.get(&Error::ConnectRefused));
+    assert_eq!(3, ctx.failures.connect);
+    assert_eq!(0, ctx.sent_requests);
+}
+
+#[test]
+#[serial]
+fn should_count_premature_close() {
+    let addr: SocketAddr = "127.0.0.1:3003".parse().unwrap();
+    let server = create_server(
+        addr,
+        2,
+        b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nhello",
+    );
+    let ctx = bench(addr, 2);
+    server.join().expect("Server failed");
+
+    assert_eq!(Some(&2), ctx.errors.get(&Error::PrematureClose));
+    assert_eq!(2, ctx.failures.receive);
+    assert_eq!(0, ctx.successful_responses);
+}
+
+#[test]
+#[serial]
+fn should_count_parse_errors() {
+    let addr: SocketAddr = "127.0.0.1:3003".parse().unwrap();
+    let server = create_server(addr, 2, b"garbage\r\n\r\n");
+    let ctx = bench(addr, 2);
+    server.join().expect("Server failed");
+
+    assert_eq!(Some(&2), ctx.errors.get(&Error::ParseError));
+    assert_eq!(2, ctx.failures.exceptions);
+}
+
+#[test]
+#[serial]
+fn should_time_out_stalled_requests() {
+    let addr: SocketAddr = "127.0.0.1:3003".parse().unwrap();
+    let listener = TcpListener::bind(addr).expect("Failed to bind");
+    let server = thread::spawn(move || {
+        // accept, but never respond
+        let connections: Vec<_> = (0..2).map(|_| listener.accept()).collect();
+        thread::sleep(Duration::from_secs(1));
+        drop(connections);
+    });
+
+    let ctx = bench_with_timeout(addr, 2, Duration::from_millis(200));
+    server.join().expect("Server failed");
+
+    assert_eq!(Some(&2), ctx.errors.get(&Error::ReadTimeout));
+    assert_eq!(2, ctx.failures.receive);
+    assert_eq!(2, ctx.sent_requests);
+}
+
+fn bench(addr: SocketAddr, requests: usize) -> Ctx {
+    bench_with_timeout(addr, requests, Duration::from_secs(30))
+}
+
+fn bench_with_timeout(addr: SocketAddr, requests: usize, timeout: Duration) -> Ctx {
+    let url = Url::parse(&format!("http://{}", addr)).expect("Invalid url");
+    let reporter = Rc::new(RefCell::new(Reporter::new(None, 3)));
+    let request = Request::new(Method::Get, &url);
+    let mut ctx = Ctx::new(request.to_bytes(), requests, 1).unwrap();
+    ctx.timeout = timeout;
+    let conn = Connection::new(
+        &mut ctx,
+        addr,
+        Box::new(TcpStream::connect),
+        reporter.clone(),
+    )
+    .expect("Failed to create connection");
+    let mut connections = HashMap::new();
+    connections.insert(conn.token, conn);
+
+    benchmark(Duration::from_secs(5), &mut ctx, &mut connections, reporter)
+        .expect("Failed benchmark");
+    ctx
+}
+
+/// Answers each request with the response and closes the connection
+fn create_server(addr: SocketAddr, requests: usize, response: &'static [u8]) -> JoinHandle<()> {
+    let listener = TcpListener::bind(addr).expect("Failed to bind");
+
+    thread::spawn(move || {
+        for _ in 0..requests {
+            let (mut sock, _) = listener.accept().expect("Failed to accept");
+
+            let mut request = vec![];
+            let mut buf = [0; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let n = sock.read(&mut buf).expect("Failed to read request");
+                request.extend_from_slice(&buf[..n]);
+            }
+
+            sock.write_all(response).expect("Failed to write response");
+        }
+    })
+}
@@ -0,0 +1,40 @@
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::process::Command;
+use std::thread;
+
+fn rab(args: &[&str]) -> Option<i32> {
+    Command::new(env!("CARGO_BIN_EXE_rab"))
+        .args(args)
+        .output()
+        .expect("Failed to run rab")
+        .status
+        .code()
+}
+
+#[test]
+fn should_exit_with_1_on_invalid_arguments() {
+    // nothing is sent, the arguments are checked first
+    assert_eq!(Some(1), rab(&["-n", "1", "-c", "2", "127.0.0.1:3004"]));
+    assert_eq!(
+        Some(1),
+        rab(&["-n", "4", "-c", "2", "--threads", "3", "127.0.0.1:3004"])
+    );
+}
+
+#[test]
+fn should_exit_with_2_on_failed_assertion() {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind");
+    let addr = listener.local_addr().unwrap().to_string();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.expect("Failed to accept");
+            let mut buf = [0; 1024];
+            let _ = stream.read(&mut buf);
+            let _ = stream.write_all(b"HTTP/1.0 200 OK\r\nContent-Length: 2\r\n\r\nok");
+        }
+    });
+
+    assert_eq!(Some(0), rab(&["-n", "2", "--assert", "errors<1", &addr]));
+    assert_eq!(Some(2), rab(&["-n", "2", "--assert", "rps<0", &addr]));
+}
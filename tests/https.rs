@@ -0,0 +1,88 @@
+extern crate rab;
+extern crate serial_test;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use rustls::{
+    Certificate, NoClientAuth, PrivateKey, ServerConfig, ServerSession, Session, StreamOwned,
+};
+use serial_test::serial;
+use url::Url;
+
+use rab::benchmarking::benchmark;
+use rab::connection::Connection;
+use rab::ctx::Ctx;
+use rab::http::{Method, Request};
+use rab::reporting::Reporter;
+use rab::tls;
+
+#[test]
+#[serial]
+fn should_benchmark_over_tls() {
+    let url = Url::parse("https://localhost:3001").expect("Invalid url");
+    let addr: SocketAddr = "127.0.0.1:3001".parse().unwrap();
+    let server = create_tls_server(addr, 2);
+
+    let reporter = Rc::new(RefCell::new(Reporter::new(None, 3)));
+    let request = Request::new(Method::Get, &url);
+    let mut ctx = Ctx::new(request.to_bytes(), 2, 1).unwrap();
+    let factory = tls::connector("localhost", true).expect("Failed to create connector");
+    let conn = Connection::new(&mut ctx, addr, Box::new(factory), reporter.clone())
+        .expect("Failed to create connection");
+    let mut connections = HashMap::new();
+    connections.insert(conn.token, conn);
+
+    benchmark(Duration::from_secs(5), &mut ctx, &mut connections, reporter)
+        .expect("Failed benchmark");
+    server.join().expect("Server failed");
+
+    assert_eq!(2, ctx.successful_responses);
+    assert_eq!(Some("tlssrv".into()), ctx.server_name);
+    assert!(ctx.tls_protocol.unwrap().starts_with("TLSv1.3,"));
+}
+
+#[test]
+fn should_refuse_ip_address_without_insecure() {
+    assert!(tls::connector("127.0.0.1", false).is_err());
+    assert!(tls::connector("127.0.0.1", true).is_ok());
+}
+
+fn create_tls_server(addr: SocketAddr, requests: usize) -> JoinHandle<()> {
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config
+        .set_single_cert(
+            vec![Certificate(cert.serialize_der().unwrap())],
+            PrivateKey(cert.serialize_private_key_der()),
+        )
+        .expect("Invalid certificate");
+    let config = Arc::new(config);
+    let listener = TcpListener::bind(addr).expect("Failed to bind");
+
+    thread::spawn(move || {
+        for _ in 0..requests {
+            let (sock, _) = listener.accept().expect("Failed to accept");
+            let mut tls = StreamOwned::new(ServerSession::new(&config), sock);
+
+            let mut request = vec![];
+            let mut buf = [0; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let n = tls.read(&mut buf).expect("Failed to read request");
+                request.extend_from_slice(&buf[..n]);
+            }
+
+            tls.write_all(b"HTTP/1.0 200 OK\r\nServer: tlssrv\r\n\r\nhello")
+                .expect("Failed to write response");
+            tls.sess.send_close_notify();
+            tls.flush().expect("Failed to flush");
+        }
+    })
+}
@@ -4,9 +4,14 @@ extern crate serial_test;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::convert::Infallible;
+use std::io::{Read, Write};
+use std::net::TcpListener;
 use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::mpsc::channel;
-use std::time::Duration;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use hyper::service::{make_service_fn, service_fn};
 use hyper::{Body, Response, Server};
@@ -19,9 +24,11 @@ use url::Url;
 
 use rab::benchmarking::benchmark;
 use rab::connection::Connection;
-use rab::ctx::Ctx;
-use rab::http::create_request;
+use rab::ctx::{Budget, Ctx, Warmup};
+use rab::http::{Method, Request};
+use rab::profile::{Profile, Shape};
 use rab::reporting::Reporter;
+use rab::schedule::Schedule;
 
 #[tokio::test(flavor = "multi_thread")]
 #[serial]
@@ -50,10 +57,235 @@ async fn should_read_server_name() {
     let _ = server.await;
 }
 
+#[tokio::test(flavor = "multi_thread")]
+#[serial]
+async fn should_reuse_keep_alive_connection() {
+    let url = Url::parse("http://localhost:3000").expect("Invalid url");
+    let (server, tx_done) = create_server(&url, || Response::new(Body::from("hello, world")));
+    let ctx = (*bench_keep_alive_connection(&url, 5)).0;
+    tx_done.send(1).expect("Failed to signal done");
+    assert_eq!(5, ctx.successful_responses);
+    assert_eq!(5, ctx.keep_alive_responses);
+    let _ = server.await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial]
+async fn should_count_length_failures() {
+    static REQUESTS: AtomicUsize = AtomicUsize::new(0);
+    let url = Url::parse("http://localhost:3000").expect("Invalid url");
+    let (server, tx_done) = create_server(&url, || {
+        let body = if REQUESTS.fetch_add(1, Ordering::SeqCst).is_multiple_of(2) {
+            "short"
+        } else {
+            "a bit longer"
+        };
+        Response::new(Body::from(body))
+    });
+    let ctx = (*bench_keep_alive_connection(&url, 4)).0;
+    tx_done.send(1).expect("Failed to signal done");
+    assert_eq!(Some(5), ctx.doclen);
+    assert_eq!(4, ctx.successful_responses);
+    assert_eq!(2, ctx.failures.length);
+    assert_eq!(2, ctx.failures.total());
+    let _ = server.await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial]
+async fn should_send_at_fixed_rate() {
+    let url = Url::parse("http://localhost:3000").expect("Invalid url");
+    let (server, tx_done) = create_server(&url, || Response::new(Body::from("hello, world")));
+    let started = Instant::now();
+    let ctx = (*bench_with(&url, 5, true, |ctx| {
+        ctx.schedule = Some(Schedule::new(100.0));
+    }))
+    .0;
+    let elapsed = started.elapsed();
+    tx_done.send(1).expect("Failed to signal done");
+    assert_eq!(5, ctx.successful_responses);
+    assert!(elapsed >= Duration::from_millis(40), "took {:?}", elapsed);
+    let _ = server.await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial]
+async fn should_limit_connections_at_fixed_rate() {
+    let url = Url::parse("http://localhost:3000").expect("Invalid url");
+    let (server, tx_done) = create_server(&url, || {
+        // much slower than the rate
+        thread::sleep(Duration::from_millis(20));
+        Response::new(Body::from("hello, world"))
+    });
+    let ctx = (*bench_with(&url, 10, true, |ctx| {
+        ctx.schedule = Some(Schedule::new(1000.0));
+        ctx.max_connections = 2;
+    }))
+    .0;
+    tx_done.send(1).expect("Failed to signal done");
+    assert_eq!(10, ctx.successful_responses);
+    assert_eq!(2, ctx.connections_opened());
+    let _ = server.await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial]
+async fn should_ramp_up_rate() {
+    let url = Url::parse("http://localhost:3000").expect("Invalid url");
+    let (server, tx_done) = create_server(&url, || Response::new(Body::from("hello, world")));
+    let profile = Profile::new(Shape::Ramp(Duration::from_millis(100)), 200.0);
+    let ctx = (*bench_with(&url, 30, true, |ctx| {
+        ctx.schedule = Some(Schedule::new(200.0));
+        ctx.profile = Some(profile);
+    }))
+    .0;
+    tx_done.send(1).expect("Failed to signal done");
+    assert_eq!(30, ctx.successful_responses);
+    assert_eq!(Some(200.0), ctx.schedule.map(|s| s.rate()));
+    let _ = server.await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial]
+async fn should_discard_warmup() {
+    static REQUESTS: AtomicUsize = AtomicUsize::new(0);
+    let url = Url::parse("http://localhost:3000").expect("Invalid url");
+    let (server, tx_done) = create_server(&url, || {
+        let body = if REQUESTS.fetch_add(1, Ordering::SeqCst) < 3 {
+            "cold"
+        } else {
+            "hello, world"
+        };
+        Response::new(Body::from(body))
+    });
+    let ctx = (*bench_with(&url, 4, true, |ctx| {
+        ctx.warmup = Some(Warmup::Requests(3));
+    }))
+    .0;
+    tx_done.send(1).expect("Failed to signal done");
+    assert_eq!(7, REQUESTS.load(Ordering::SeqCst));
+    assert_eq!(Some(12), ctx.doclen);
+    assert_eq!(4, ctx.sent_requests);
+    assert_eq!(4, ctx.successful_responses);
+    assert_eq!(0, ctx.failures.total());
+    let _ = server.await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial]
+async fn should_share_requests_between_threads() {
+    let url = Url::parse("http://localhost:3000").expect("Invalid url");
+    let (server, tx_done) = create_server(&url, || Response::new(Body::from("hello, world")));
+    let budget = Arc::new(Budget::new(25));
+    let threads: Vec<_> = (0..3)
+        .map(|_| {
+            let url = url.clone();
+            let budget = budget.clone();
+            thread::spawn(move || {
+                let ctx = (*bench_with(&url, 25, true, |ctx| ctx.budget = Some(budget))).0;
+                ctx.successful_responses
+            })
+        })
+        .collect();
+    let responses: usize = threads
+        .into_iter()
+        .map(|t| t.join().expect("Benchmark thread panicked"))
+        .sum();
+    tx_done.send(1).expect("Failed to signal done");
+    assert_eq!(25, responses);
+    let _ = server.await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[serial]
+async fn should_run_until_time_limit() {
+    let url = Url::parse("http://localhost:3000").expect("Invalid url");
+    let (server, tx_done) = create_server(&url, || Response::new(Body::from("hello, world")));
+    let started = Instant::now();
+    let ctx = (*bench_for(&url, usize::MAX, Duration::from_millis(200), |_| {})).0;
+    let elapsed = started.elapsed();
+    tx_done.send(1).expect("Failed to signal done");
+    assert!(elapsed >= Duration::from_millis(200), "took {:?}", elapsed);
+    assert!(ctx.successful_responses > 1);
+    // the request in flight at the time limit was waited for
+    assert_eq!(ctx.sent_requests, ctx.total_responses());
+    let _ = server.await;
+}
+
+#[test]
+#[serial]
+fn should_post_body_larger_than_send_buffer() {
+    let url = Url::parse("http://localhost:3000").expect("Invalid url");
+    let mut request = Request::new(Method::Post, &url);
+    request.set_body(vec![b'x'; 16 * 1024 * 1024], "text/plain");
+    let payload = request.to_bytes();
+    let len = payload.len();
+
+    let listener = TcpListener::bind(url.socket_addrs(|| None).unwrap()[0]).unwrap();
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().expect("Failed to accept");
+        // a slow server, the socket buffers fill up meanwhile
+        thread::sleep(Duration::from_millis(100));
+        let mut buf = vec![0; 64 * 1024];
+        let mut received = 0;
+        while received < len {
+            match stream.read(&mut buf).expect("Failed to read request") {
+                0 => break,
+                n => received += n,
+            }
+        }
+        stream
+            .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok")
+            .expect("Failed to respond");
+        received
+    });
+
+    let ctx = (*bench_with(&url, 1, false, |ctx| ctx.payload = payload)).0;
+    assert_eq!(len, server.join().expect("Server panicked"));
+    assert_eq!(1, ctx.successful_responses);
+    assert_eq!(0, ctx.failures.total());
+}
+
 fn bench_connection(url: &Url) -> Box<(Ctx, Connection<TcpStream>)> {
-    let reporter = Rc::new(RefCell::new(Reporter::new(None)));
-    let request = create_request(&url, false);
-    let mut ctx = Ctx::new(request.into_bytes(), 1, 1).unwrap();
+    bench(url, 1, false)
+}
+
+fn bench_keep_alive_connection(url: &Url, requests: usize) -> Box<(Ctx, Connection<TcpStream>)> {
+    bench(url, requests, true)
+}
+
+fn bench(url: &Url, requests: usize, keep_alive: bool) -> Box<(Ctx, Connection<TcpStream>)> {
+    bench_with(url, requests, keep_alive, |_| {})
+}
+
+fn bench_with(
+    url: &Url,
+    requests: usize,
+    keep_alive: bool,
+    setup: impl FnOnce(&mut Ctx),
+) -> Box<(Ctx, Connection<TcpStream>)> {
+    bench_for(url, requests, Duration::from_secs(5), |ctx| {
+        ctx.keep_alive = keep_alive;
+        setup(ctx)
+    })
+}
+
+fn bench_for(
+    url: &Url,
+    requests: usize,
+    timelimit: Duration,
+    setup: impl FnOnce(&mut Ctx),
+) -> Box<(Ctx, Connection<TcpStream>)> {
+    let reporter = Rc::new(RefCell::new(Reporter::new(None, 3)));
+    let mut ctx = Ctx::new(vec![], requests, 1).unwrap();
+    setup(&mut ctx);
+    if ctx.payload.is_empty() {
+        let mut request = Request::new(Method::Get, url);
+        if ctx.keep_alive {
+            request.keep_alive();
+        }
+        ctx.payload = request.to_bytes();
+    }
     let conn = Connection::new(
         &mut ctx,
         url.socket_addrs(|| None).unwrap()[0],
@@ -65,15 +297,14 @@ fn bench_connection(url: &Url) -> Box<(Ctx, Connection<TcpStream>)> {
     let mut connections = HashMap::new();
     connections.insert(conn.token, conn);
 
-    benchmark(Duration::from_secs(5), &mut ctx, &mut connections, reporter)
-        .expect("Failed benchmark");
+    benchmark(timelimit, &mut ctx, &mut connections, reporter).expect("Failed benchmark");
 
     Box::new((ctx, connections.remove(&token).unwrap()))
 }
 
-fn create_server<R: 'static>(url: &Url, resp: R) -> (JoinHandle<()>, oneshot::Sender<u8>)
+fn create_server<R>(url: &Url, resp: R) -> (JoinHandle<()>, oneshot::Sender<u8>)
 where
-    R: Fn() -> Response<Body> + Send + Clone + Copy,
+    R: Fn() -> Response<Body> + Send + Clone + Copy + 'static,
 {
     let (tx_started, rx_started) = channel();
     let (tx_done, rx_done) = oneshot::channel::<u8>();